@@ -1,55 +1,536 @@
-use clap::{Parser, Subcommand};
-use llms::{qdrant::Qdrant, Embedder};
+use clap::{Parser, Subcommand, ValueEnum};
+use llms::config::{Fusion, GroupBy, StoreBackend};
+use llms::export::{self, ExportReader};
+use llms::filter::{SearchFilter, Selection};
+use llms::qdrant::Qdrant;
+use llms::similar::{read_range, Seed};
+use llms::stats::Stats;
+use llms::store::{
+    AnyStore, CollectionStatus, InsertProgress, SearchMode, SearchOptions, SearchResult,
+    VectorStore,
+};
+use llms::{
+    cache::EmbeddingCache, config::Config, rerank::Reranker, schema::Schema, Embedder, Embedding,
+};
+use parser::archive::{FetchOptions, Progress};
+use parser::cache::SourceCache;
+use parser::discussion::link_discussions;
 use parser::github::GitHub;
-use std::path::PathBuf;
+use parser::manifest::Manifest;
+use parser::source::{AnyProvider, ProviderKind, Repository, SourceProvider};
+use parser::{CodeType, Document, DocumentKind, TCode, Visibility};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::error::Error;
+use crate::sync::sync_manifest;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     #[command(subcommand)]
     command: Commands,
+    /// Where fetched sources are cached (defaults to $DUCKYDUCK_CACHE_DIR or the user cache dir)
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Config file (defaults to duckyduck.toml in the working directory, if present)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// The namespace to index into and search, instead of `qdrant.namespace` in the config
+    #[arg(long, global = true)]
+    namespace: Option<String>,
+    /// Keep the index in a local file instead of Qdrant, as with `store.backend = "local"`
+    #[arg(long, global = true)]
+    local: bool,
 }
 
 impl Args {
     pub async fn process(self) -> Result<(), Error> {
+        let cache = SourceCache::new(self.cache_dir.unwrap_or_else(SourceCache::default_dir));
+        let mut config =
+            Config::load(self.config.as_deref()).map_err(|x| Error::Initialise(x.to_string()))?;
+        if self.namespace.is_some() {
+            config.qdrant.namespace = self.namespace;
+        }
+        if self.local {
+            config.store.backend = StoreBackend::Local;
+        }
+
         match self.command {
             Commands::Embed { dir } => {
-                let items = parser::process_dir(dir);
+                let items = process_dir(dir, &|_| true)?;
 
-                let embedder = Embedder::new().map_err(|x| Error::Initialise(x.to_string()))?;
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
 
                 let embeddings = embedder
                     .embed_code(items)
-                    .map_err(|_| Error::Parsing)
-                    .unwrap();
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                let store = connect(&config)?;
+                ensure_collection(&store, &embedder).await?;
+
+                insert_docs(&store, embeddings).await?;
+                print_cache_stats(&embedder);
+            }
+            Commands::Fetch {
+                org,
+                repo,
+                provider,
+                url,
+                reference,
+                max_download_mb,
+                max_unpacked_mb,
+            } => {
+                let repo = Repository::new(org, repo);
+                let reference = reference.as_deref();
+                let options = FetchOptions {
+                    max_download_bytes: max_download_mb.map(|mb| mb * 1024 * 1024),
+                    max_unpacked_bytes: max_unpacked_mb.map(|mb| mb * 1024 * 1024),
+                    ..Default::default()
+                }
+                .with_progress(print_progress);
+
+                let provider = AnyProvider::new(provider.into(), url)
+                    .map_err(|x| Error::Initialise(x.to_string()))?;
+
+                fetch_source(&provider, &cache, &repo, reference, &options).await?;
+            }
+            Commands::Discussions { org, repo, dir } => {
+                let repo = Repository::new(org, repo);
+
+                println!("Fetching issues and pull requests for {repo}...");
+                let mut discussions = GitHub::from_env()
+                    .map_err(|x| Error::Initialise(x.to_string()))?
+                    .on_rate_limit(|wait| {
+                        println!("GitHub rate limit hit, waiting {}s...", wait.as_secs())
+                    })
+                    .fetch_discussions(&repo)
+                    .await
+                    .map_err(|x| Error::Fetch(x.to_string()))?;
+                println!("Fetched {} discussions.", discussions.len());
+
+                if let Some(dir) = dir {
+                    let code = process_dir(dir, &|_| true)?;
+                    link_discussions(&mut discussions, &code);
+                }
+
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
+
+                let embeddings = embedder
+                    .embed_documents(discussions.into_iter().map(Document::Discussion).collect())
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                let store = connect(&config)?;
+                ensure_collection(&store, &embedder).await?;
+
+                insert_docs(&store, embeddings).await?;
+                print_cache_stats(&embedder);
+            }
+            Commands::Sync {
+                manifest,
+                interval_minutes,
+            } => {
+                let manifest =
+                    Manifest::from_path(manifest).map_err(|x| Error::Initialise(x.to_string()))?;
+
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
+
+                let store = connect(&config)?;
+
+                loop {
+                    let res = sync_manifest(&manifest, &cache, &embedder, &store).await;
+                    print_cache_stats(&embedder);
+
+                    match interval_minutes {
+                        Some(minutes) => {
+                            if let Err(err) = res {
+                                println!("{err}");
+                            }
+                            println!("Next sync in {minutes} minutes.");
+                            tokio::time::sleep(Duration::from_secs(minutes * 60)).await;
+                        }
+                        None => break res?,
+                    }
+                }
+            }
+            Commands::Cache { command } => match command {
+                CacheCommands::List => {
+                    for entry in cache.entries().map_err(|x| Error::Cache(x.to_string()))? {
+                        println!(
+                            "{}/{} {} {} KiB {}",
+                            entry.provider,
+                            entry.repo,
+                            entry.sha,
+                            entry.size / 1024,
+                            entry.path.display()
+                        );
+                    }
+                }
+                CacheCommands::Prune {
+                    keep_latest,
+                    older_than_days,
+                } => {
+                    let older_than =
+                        older_than_days.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+                    let removed = cache
+                        .prune(keep_latest, older_than)
+                        .map_err(|x| Error::Cache(x.to_string()))?;
+
+                    for entry in &removed {
+                        println!("Removed {}/{} {}", entry.provider, entry.repo, entry.sha);
+                    }
+                    println!("Pruned {} cached checkouts.", removed.len());
+                }
+            },
+            Commands::EmbeddingCache { command } => {
+                let Some(cache) = EmbeddingCache::from_config(&config.embedding_cache) else {
+                    println!("The embedding cache is disabled.");
+                    return Ok(());
+                };
+
+                match command {
+                    EmbeddingCacheCommands::Stats => {
+                        let usage = cache.usage().map_err(|x| Error::Cache(x.to_string()))?;
+                        for model in &usage {
+                            println!(
+                                "{} {} embeddings {} KiB",
+                                model.model,
+                                model.entries,
+                                model.bytes / 1024
+                            );
+                        }
+                        println!(
+                            "{} embeddings cached in {}",
+                            usage.iter().map(|model| model.entries).sum::<u64>(),
+                            cache.root().display()
+                        );
+                    }
+                    EmbeddingCacheCommands::Clear => {
+                        cache.clear().map_err(|x| Error::Cache(x.to_string()))?;
+                        println!("Cleared {}", cache.root().display());
+                    }
+                }
+            }
+
+            Commands::Search {
+                prompt,
+                mode,
+                fusion,
+                weights,
+                filter,
+                rerank,
+                candidates,
+                top_k,
+                rescore,
+                oversampling,
+                offset,
+                group_by,
+                group_size,
+            } => {
+                let mut config = config;
+                config.rerank.enabled |= rerank;
 
-                let qdrant = Qdrant::from_url("http://localhost:6334", None).unwrap();
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
+                let reranker =
+                    Reranker::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
 
-                // qdrant.create_collection().await.unwrap();
+                let mut options = SearchOptions::from_config(&config.search);
+                options.mode = mode.into();
+                if let Some(fusion) = fusion {
+                    options.fusion = fusion.into();
+                }
+                options.weights.extend(weights);
+                options.filter = filter.into();
+                options.rescore = rescore.or(options.rescore);
+                options.oversampling = oversampling.or(options.oversampling);
+                options.offset = offset;
+                if let Some(group_by) = group_by {
+                    options.group_by = Some(group_by.into());
+                }
+                if let Some(group_size) = group_size {
+                    options.group_size = group_size;
+                }
+                let retrieval = match &reranker {
+                    Some(reranker) => reranker.candidate_options(&options, candidates),
+                    None => SearchOptions {
+                        limit: candidates.unwrap_or(options.limit),
+                        ..options.clone()
+                    },
+                };
 
-                qdrant.insert_docs(embeddings).await.unwrap();
+                let embedding = embedder
+                    .embed_prompt(prompt.clone())
+                    .map_err(|x| Error::Search(x.to_string()))?;
+
+                let store = connect(&config)?;
+                ensure_collection(&store, &embedder).await?;
+
+                let mut res = store
+                    .search(embedding, &retrieval)
+                    .await
+                    .map_err(|x| Error::Search(x.to_string()))?;
+                if let Some(reranker) = reranker {
+                    let top_k = top_k.unwrap_or(reranker.top_k());
+                    res = reranker
+                        .rerank_page(&prompt, res, &options, top_k)
+                        .map_err(|x| Error::Search(x.to_string()))?;
+                }
+
+                let mut group = None;
+                for result in res {
+                    if result.group.is_some() && result.group != group {
+                        group.clone_from(&result.group);
+                        println!("== {}", group.as_deref().unwrap_or_default());
+                    }
+                    print_result(&result);
+                }
+            }
+
+            Commands::Similar {
+                seed,
+                vector,
+                limit,
+                dir,
+                filter,
+            } => {
+                let mut options = SearchOptions::from_config(&config.search);
+                options.filter = filter.into();
+                if let Some(limit) = limit {
+                    options.limit = limit;
+                }
+
+                let store = connect(&config)?;
+
+                let repo = options.filter.repo.as_deref();
+                let seeds = store
+                    .lookup(&seed, &vector, repo)
+                    .await
+                    .map_err(|x| Error::Search(x.to_string()))?;
+
+                let (embedding, exclude) = match (&seed, seeds.first()) {
+                    // every point overlapping the range is part of it
+                    (Seed::Range { .. }, Some(point)) => {
+                        let exclude = seeds.iter().map(|point| point.id.clone()).collect();
+                        (point.vector.clone(), exclude)
+                    }
+                    (_, Some(point)) => (point.vector.clone(), vec![point.id.clone()]),
+                    (Seed::Range { file, from, to }, None) => {
+                        let text = read_range(&dir, file, *from, *to)
+                            .map_err(|x| Error::Search(x.to_string()))?;
+                        let embedder = Embedder::from_config(&config)
+                            .map_err(|x| Error::Initialise(x.to_string()))?;
+                        let embedding = embedder
+                            .embed_passage(&vector, text)
+                            .map_err(|x| Error::Search(x.to_string()))?;
+                        (embedding, Vec::new())
+                    }
+                    (_, None) => return Err(Error::Search(format!("{seed} is not indexed"))),
+                };
+
+                let res = store
+                    .similar(&vector, embedding, &exclude, &options)
+                    .await
+                    .map_err(|x| Error::Search(x.to_string()))?;
+                for result in res {
+                    print_result(&result);
+                }
+            }
+
+            Commands::Remove { selection } => {
+                let selection: Selection = selection.into();
+                if selection.is_empty() {
+                    return Err(Error::Index(
+                        "select what to remove, e.g. with --repo or --file".to_string(),
+                    ));
+                }
+
+                let store = connect(&config)?;
+                let deleted = store
+                    .delete(&selection)
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+                println!("Removed {deleted} points from {}.", store.collection());
+            }
+
+            Commands::Prune { dir, repo } => {
+                let store = connect(&config)?;
+                let pruned = store
+                    .prune(&dir, repo.as_deref())
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                for file in &pruned.files {
+                    println!("Removed {file}");
+                }
+                println!(
+                    "Pruned {} points of {} files that no longer exist.",
+                    pruned.points,
+                    pruned.files.len()
+                );
             }
-            Commands::Fetch { org, repo } => {
-                println!("Fetching {org}/{repo}...");
-                let github = GitHub::new();
-                let bytes = github.fetch_repo("tokio-rs", "tokio").await;
-                println!("Repo has been fetched.");
-
-                github.unpack_repo("./things", bytes).await;
-                println!("Repo has been unpacked.")
+
+            Commands::Migrate { keep_old } => {
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
+                let store = connect(&config)?;
+
+                println!("Re-embedding {}...", store.collection());
+                let migration = store
+                    .migrate(&embedder, keep_old, |documents| {
+                        print!("\r{documents} documents");
+                        let _ = std::io::stdout().flush();
+                    })
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+                println!();
+
+                println!(
+                    "Migrated {} documents from {} to {}.",
+                    migration.documents, migration.from, migration.to
+                );
+            }
+
+            Commands::Export { output } => {
+                let store = connect(&config)?;
+                let file = std::fs::File::create(&output)
+                    .map_err(|x| Error::Index(format!("{}: {x}", output.display())))?;
+                let points = export::export(&store, file)
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                println!(
+                    "Exported {points} points of {} to {}.",
+                    store.collection(),
+                    output.display()
+                );
+            }
+
+            Commands::Import { input } => {
+                let file = std::fs::File::open(&input)
+                    .map_err(|x| Error::Index(format!("{}: {x}", input.display())))?;
+                let reader = ExportReader::new(std::io::BufReader::new(file))
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                let embedder =
+                    Embedder::from_config(&config).map_err(|x| Error::Initialise(x.to_string()))?;
+                reader
+                    .check(&Schema::new(&embedder))
+                    .map_err(|x| Error::Index(x.to_string()))?;
+
+                let store = connect(&config)?;
+                ensure_collection(&store, &embedder).await?;
+
+                println!(
+                    "Importing {} into {}...",
+                    reader.header().collection,
+                    store.collection()
+                );
+                let points =
+                    export::import(&store, reader, config.qdrant.upsert.batch_size, |points| {
+                        print!("\r{points} points");
+                        let _ = std::io::stdout().flush();
+                    })
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+                println!();
+
+                println!("Imported {points} points.");
             }
 
-            Commands::Search { prompt } => {
-                let embedder = Embedder::new().map_err(|x| Error::Initialise(x.to_string()))?;
+            Commands::Snapshot { command } => {
+                if config.store.backend != StoreBackend::Qdrant {
+                    return Err(Error::Initialise(
+                        "snapshots are only supported by the Qdrant backend".to_string(),
+                    ));
+                }
+                let qdrant = Qdrant::from_config(&config.qdrant)
+                    .map_err(|x| Error::Initialise(x.to_string()))?;
+
+                match command {
+                    SnapshotCommands::Create { output } => {
+                        let snapshot = qdrant
+                            .create_snapshot()
+                            .await
+                            .map_err(|x| Error::Index(x.to_string()))?;
+                        println!(
+                            "Created snapshot {} of {} ({} KiB)",
+                            snapshot.name,
+                            qdrant.collection(),
+                            snapshot.size / 1024
+                        );
 
-                let embedding = embedder.embed_prompt(prompt).unwrap();
+                        if let Some(output) = output {
+                            download_snapshot(&qdrant, &snapshot.name, &output).await?;
+                        }
+                    }
+                    SnapshotCommands::List => {
+                        let snapshots = qdrant
+                            .list_snapshots()
+                            .await
+                            .map_err(|x| Error::Index(x.to_string()))?;
+                        for snapshot in &snapshots {
+                            println!(
+                                "{} {} KiB {}",
+                                snapshot.name,
+                                snapshot.size / 1024,
+                                snapshot.checksum.as_deref().unwrap_or("-")
+                            );
+                        }
+                        println!("{} snapshots of {}", snapshots.len(), qdrant.collection());
+                    }
+                    SnapshotCommands::Download { name, output } => {
+                        let name = match name {
+                            Some(name) => name,
+                            None => qdrant
+                                .list_snapshots()
+                                .await
+                                .map_err(|x| Error::Index(x.to_string()))?
+                                .into_iter()
+                                .next()
+                                .map(|snapshot| snapshot.name)
+                                .ok_or_else(|| {
+                                    Error::Index(format!(
+                                        "{} has no snapshots",
+                                        qdrant.collection()
+                                    ))
+                                })?,
+                        };
+                        download_snapshot(&qdrant, &name, &output).await?;
+                    }
+                    SnapshotCommands::Restore { file, keep_old } => {
+                        println!("Uploading {}...", file.display());
+                        let restore = qdrant
+                            .restore_snapshot(&file, keep_old)
+                            .await
+                            .map_err(|x| Error::Index(x.to_string()))?;
 
-                let qdrant = Qdrant::from_url("http://localhost:6334", None).unwrap();
+                        match restore.from {
+                            Some(from) => println!(
+                                "Restored {} into {}, replacing {from}.",
+                                file.display(),
+                                restore.to
+                            ),
+                            None => {
+                                println!("Restored {} into {}.", file.display(), restore.to)
+                            }
+                        }
+                    }
+                }
+            }
 
-                let res = qdrant.search(embedding).await.unwrap();
-                println!("{:?}", res);
+            Commands::Stats => {
+                let store = connect(&config)?;
+                let stats = Stats::collect(&store)
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+                print_stats(&stats);
             }
         }
 
@@ -57,6 +538,443 @@ impl Args {
     }
 }
 
+/// Parses the files of `dir` that `filter` lets through, telling which ones had to be skipped.
+pub(crate) fn process_dir(
+    dir: PathBuf,
+    filter: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<TCode>, Error> {
+    let (code, skipped) =
+        parser::process_dir_with(dir, filter).map_err(|x| Error::Index(x.to_string()))?;
+    for (path, reason) in skipped {
+        println!("Skipping {}: {reason}", path.display());
+    }
+
+    Ok(code)
+}
+
+fn connect(config: &Config) -> Result<AnyStore, Error> {
+    AnyStore::from_config(config).map_err(|x| Error::Initialise(x.to_string()))
+}
+
+/// Creates the collection if needed, and fails if it was indexed with other models.
+pub(crate) async fn ensure_collection(store: &AnyStore, embedder: &Embedder) -> Result<(), Error> {
+    let status = store
+        .ensure_collection(embedder)
+        .await
+        .map_err(|x| Error::Initialise(x.to_string()))?;
+
+    match status {
+        CollectionStatus::Created => println!("Created collection {}.", store.collection()),
+        CollectionStatus::Outdated(reasons) => println!(
+            "Collection {} should be migrated: {}. Run `migrate` to re-embed it.",
+            store.collection(),
+            reasons.join(", ")
+        ),
+        CollectionStatus::Ready => {}
+    }
+
+    Ok(())
+}
+
+/// Prints a result's score, ID and the scores behind it, followed by its document.
+fn print_result(result: &SearchResult) {
+    let mut scores: Vec<String> = result
+        .scores
+        .iter()
+        .map(|(vector, score)| format!("{vector} {score:.4}"))
+        .collect();
+    scores.sort();
+    if let Some(score) = result.rerank_score {
+        scores.push(format!("rerank {score:.4}"));
+    }
+
+    println!("{:.4} {} ({})", result.score, result.id, scores.join(", "));
+    println!("{:?}", result.document);
+}
+
+async fn fetch_source(
+    provider: &impl SourceProvider,
+    cache: &SourceCache,
+    repo: &Repository,
+    reference: Option<&str>,
+    options: &FetchOptions,
+) -> Result<(), Error> {
+    println!("Fetching {repo} from {}...", provider.kind());
+    let entry = cache
+        .fetch(provider, repo, reference, options)
+        .await
+        .map_err(|x| Error::Fetch(x.to_string()))?;
+
+    println!(
+        "\nRepo is available at commit {} in {}",
+        entry.sha,
+        entry.path.display()
+    );
+
+    Ok(())
+}
+
+async fn download_snapshot(qdrant: &Qdrant, name: &str, output: &Path) -> Result<(), Error> {
+    let size = qdrant
+        .download_snapshot(name, output)
+        .await
+        .map_err(|x| Error::Index(x.to_string()))?;
+    println!(
+        "Downloaded {name} to {} ({} KiB)",
+        output.display(),
+        size / 1024
+    );
+
+    Ok(())
+}
+
+fn print_stats(stats: &Stats) {
+    println!("{}: {} points", stats.collection, stats.points);
+    match &stats.schema {
+        Some(schema) => {
+            for (name, vector) in &schema.vectors {
+                println!(
+                    "  vector {name}: {} ({} dimensions)",
+                    vector.model, vector.dimension
+                );
+            }
+            println!(
+                "  schema version {}, templates version {}",
+                schema.version, schema.template_version
+            );
+        }
+        None => println!("  no schema recorded, run `migrate` to record one"),
+    }
+
+    print_counts("Kinds", &stats.kinds);
+    println!("Repositories:");
+    for (repo, repo_stats) in &stats.repos {
+        let name = if repo.is_empty() { "(local)" } else { repo };
+        let commit = repo_stats.commit.as_deref().unwrap_or("-");
+        let indexed = repo_stats.indexed_ago().unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {name} {} points, commit {commit}, indexed {indexed}",
+            repo_stats.points
+        );
+    }
+    print_counts("Crates", &stats.crates);
+    print_counts("Code types", &stats.code_types);
+    print_counts("Template versions", &stats.template_versions);
+    print_counts("Points missing fields", &stats.missing_fields);
+
+    if !stats.duplicates.is_empty() {
+        println!(
+            "{} duplicate points (remove them with `remove --id`):",
+            stats.duplicate_points()
+        );
+        for ids in &stats.duplicates {
+            println!("  {}", ids.join(" "));
+        }
+    }
+}
+
+fn print_counts(title: &str, counts: &BTreeMap<impl Display, u64>) {
+    if !counts.is_empty() {
+        println!("{title}:");
+        for (name, count) in counts {
+            println!("  {name} {count}");
+        }
+    }
+}
+
+fn print_cache_stats(embedder: &Embedder) {
+    if let Some(stats) = embedder.cache_stats() {
+        println!(
+            "Embedding cache: {} hits, {} misses ({:.1}% hit rate)",
+            stats.hits,
+            stats.misses,
+            stats.hit_rate() * 100.0
+        );
+    }
+}
+
+/// Stores embeddings, failing once every batch has been tried if any of them couldn't be stored.
+pub(crate) async fn insert_docs(store: &AnyStore, embeddings: Vec<Embedding>) -> Result<(), Error> {
+    let report = store
+        .insert_docs(embeddings, print_insert_progress)
+        .await
+        .map_err(|x| Error::Index(x.to_string()))?;
+    println!();
+
+    for failure in &report.failures {
+        println!(
+            "Batch {} ({} points) failed: {}",
+            failure.batch, failure.points, failure.error
+        );
+    }
+    if !report.failures.is_empty() {
+        return Err(Error::Index(format!(
+            "{} of {} points couldn't be stored",
+            report.failed(),
+            report.failed() + report.inserted
+        )));
+    }
+
+    Ok(())
+}
+
+fn print_insert_progress(
+    InsertProgress {
+        inserted,
+        failed,
+        total,
+    }: InsertProgress,
+) {
+    match failed {
+        0 => print!("\rStored {inserted}/{total} points"),
+        _ => print!("\rStored {inserted}/{total} points, {failed} failed"),
+    }
+    let _ = std::io::stdout().flush();
+}
+
+fn print_progress(Progress { downloaded, total }: Progress) {
+    let downloaded = downloaded / 1024;
+    match total {
+        Some(total) => print!("\rDownloaded {downloaded}/{} KiB", total / 1024),
+        None => print!("\rDownloaded {downloaded} KiB"),
+    }
+    let _ = std::io::stdout().flush();
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Provider {
+    Github,
+    Gitlab,
+    Gitea,
+    /// Any git URL or local bare repository
+    Git,
+}
+
+impl From<Provider> for ProviderKind {
+    fn from(provider: Provider) -> Self {
+        match provider {
+            Provider::Github => Self::Github,
+            Provider::Gitlab => Self::Gitlab,
+            Provider::Gitea => Self::Gitea,
+            Provider::Git => Self::Git,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Mode {
+    /// Only the embedding models
+    Dense,
+    /// Only exact terms and identifiers
+    Sparse,
+    /// Both
+    #[default]
+    Hybrid,
+}
+
+#[derive(clap::Args)]
+pub struct FilterArgs {
+    /// Only these kinds of documents
+    #[arg(long = "kind", value_enum)]
+    kinds: Vec<Kind>,
+    /// Only these kinds of code
+    #[arg(long = "code-type", value_enum)]
+    code_types: Vec<CodeKind>,
+    /// Only code from this crate
+    #[arg(long = "crate")]
+    crate_name: Option<String>,
+    /// Only code from this module or the modules below it, e.g. `parser::source`
+    #[arg(long)]
+    module: Option<String>,
+    /// Only documents from (or, for discussions, mentioning) files matching this glob
+    #[arg(long, value_name = "GLOB")]
+    file: Option<String>,
+    /// Only code with this visibility
+    #[arg(long, value_enum)]
+    visibility: Option<Vis>,
+    /// Only documents from this repository (`owner/name`)
+    #[arg(long)]
+    repo: Option<String>,
+    /// Only tests and test-only code
+    #[arg(long, conflicts_with = "no_tests")]
+    tests: bool,
+    /// Leave out tests and test-only code
+    #[arg(long)]
+    no_tests: bool,
+}
+
+#[derive(clap::Args)]
+pub struct SelectionArgs {
+    /// Only these kinds of documents
+    #[arg(long = "kind", value_enum)]
+    kinds: Vec<Kind>,
+    /// Documents from this repository (`owner/name`)
+    #[arg(long)]
+    repo: Option<String>,
+    /// Documents from (or, for discussions, mentioning) these files
+    #[arg(long = "file", value_name = "FILE")]
+    files: Vec<String>,
+    /// Documents from files anywhere under this directory
+    #[arg(long, value_name = "DIR")]
+    dir: Option<String>,
+    /// Code from this crate
+    #[arg(long = "crate")]
+    crate_name: Option<String>,
+    /// Code with this symbol name, e.g. `Qdrant::search`
+    #[arg(long)]
+    symbol: Option<String>,
+    /// These point IDs, as search results show them
+    #[arg(long = "id")]
+    ids: Vec<String>,
+}
+
+impl From<SelectionArgs> for Selection {
+    fn from(args: SelectionArgs) -> Self {
+        Self {
+            kinds: args.kinds.into_iter().map(Into::into).collect(),
+            repo: args.repo,
+            without_repo: false,
+            files: args.files,
+            dir: args.dir,
+            crate_name: args.crate_name,
+            symbol: args.symbol,
+            ids: args.ids,
+        }
+    }
+}
+
+impl From<FilterArgs> for SearchFilter {
+    fn from(args: FilterArgs) -> Self {
+        Self {
+            kinds: args.kinds.into_iter().map(Into::into).collect(),
+            code_types: args.code_types.into_iter().map(Into::into).collect(),
+            crate_name: args.crate_name,
+            module: args.module,
+            file: args.file,
+            visibility: args.visibility.map(Into::into),
+            repo: args.repo,
+            test: match (args.tests, args.no_tests) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Kind {
+    Code,
+    Issue,
+    PullRequest,
+    ReviewComment,
+}
+
+impl From<Kind> for DocumentKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Code => Self::Code,
+            Kind::Issue => Self::Issue,
+            Kind::PullRequest => Self::PullRequest,
+            Kind::ReviewComment => Self::ReviewComment,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CodeKind {
+    Function,
+    Struct,
+    Enum,
+    Impl,
+}
+
+impl From<CodeKind> for CodeType {
+    fn from(kind: CodeKind) -> Self {
+        match kind {
+            CodeKind::Function => Self::Function,
+            CodeKind::Struct => Self::Struct,
+            CodeKind::Enum => Self::Enum,
+            CodeKind::Impl => Self::Impl,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Vis {
+    /// `pub`
+    Public,
+    /// `pub(crate)`
+    Crate,
+    /// `pub(super)` or `pub(in path)`
+    Restricted,
+    Private,
+}
+
+impl From<Vis> for Visibility {
+    fn from(vis: Vis) -> Self {
+        match vis {
+            Vis::Public => Self::Public,
+            Vis::Crate => Self::Crate,
+            Vis::Restricted => Self::Restricted,
+            Vis::Private => Self::Private,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum FusionMethod {
+    /// Reciprocal rank fusion
+    Rrf,
+    /// Weighted sum of each vector's normalised scores
+    Weighted,
+}
+
+impl From<FusionMethod> for Fusion {
+    fn from(fusion: FusionMethod) -> Self {
+        match fusion {
+            FusionMethod::Rrf => Self::Rrf,
+            FusionMethod::Weighted => Self::Weighted,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Grouping {
+    /// The file a result is in
+    File,
+    /// The type a method is implemented on, or the pull request a review comment is on
+    Parent,
+}
+
+impl From<Grouping> for GroupBy {
+    fn from(grouping: Grouping) -> Self {
+        match grouping {
+            Grouping::File => Self::File,
+            Grouping::Parent => Self::Parent,
+        }
+    }
+}
+
+fn parse_weight(arg: &str) -> Result<(String, f32), String> {
+    let (vector, weight) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected VECTOR=WEIGHT, got `{arg}`"))?;
+    let weight = weight.parse().map_err(|x| format!("invalid weight: {x}"))?;
+
+    Ok((vector.to_string(), weight))
+}
+
+impl From<Mode> for SearchMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Dense => Self::Dense,
+            Mode::Sparse => Self::Sparse,
+            Mode::Hybrid => Self::Hybrid,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// does testing things
@@ -65,15 +983,213 @@ pub enum Commands {
         dir: PathBuf,
     },
 
-    /// Fetch a public repo from GitHub
+    /// Fetch a repo from a git forge or git remote
     Fetch {
         #[arg(short, long)]
         org: String,
         #[arg(short, long)]
         repo: String,
+        #[arg(short, long, value_enum, default_value_t = Provider::Github)]
+        provider: Provider,
+        /// API base URL of a GitHub Enterprise Server (e.g. `https://github.example.com/api/v3`),
+        /// base URL of a GitLab/Gitea instance, or the remote URL for `git`
+        /// (`{owner}` and `{name}` are substituted)
+        #[arg(short, long)]
+        url: Option<String>,
+        /// Branch, tag or commit to fetch (defaults to the default branch).
+        /// Full commit SHAs that are already cached are used without any network access.
+        #[arg(long = "ref")]
+        reference: Option<String>,
+        /// Abort if the compressed archive is larger than this
+        #[arg(long, value_name = "MB")]
+        max_download_mb: Option<u64>,
+        /// Abort if the unpacked sources are larger than this
+        #[arg(long, value_name = "MB")]
+        max_unpacked_mb: Option<u64>,
+    },
+
+    /// Embed the issues, pull requests and review comments of a GitHub repo
+    Discussions {
+        #[arg(short, long)]
+        org: String,
+        #[arg(short, long)]
+        repo: String,
+        /// A checkout of the repo, used to link discussions to the files and symbols they mention
+        #[arg(short, long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+
+    /// Fetch and re-index every repository in a manifest
+    Sync {
+        #[arg(short, long, value_name = "FILE", default_value = "repositories.toml")]
+        manifest: PathBuf,
+        /// Keep running, syncing again every this many minutes
+        #[arg(long)]
+        interval_minutes: Option<u64>,
+    },
+
+    /// Inspect or clear the cache of computed embeddings
+    EmbeddingCache {
+        #[command(subcommand)]
+        command: EmbeddingCacheCommands,
+    },
+
+    /// Inspect or clean up the cache of fetched sources
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
     },
+
     Search {
         #[arg(short, long)]
         prompt: String,
+        #[arg(long, value_enum, default_value_t)]
+        mode: Mode,
+        /// How the results of each vector are combined (defaults to `search.fusion` in the config)
+        #[arg(long, value_enum)]
+        fusion: Option<FusionMethod>,
+        /// How much a vector counts towards the fused score, e.g. `--weight nlp=0.5`
+        #[arg(long = "weight", value_name = "VECTOR=WEIGHT", value_parser = parse_weight)]
+        weights: Vec<(String, f32)>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Rerank results with the cross-encoder, even if `rerank.enabled` isn't set in the config
+        #[arg(long)]
+        rerank: bool,
+        /// How many results to retrieve (before reranking, on top of those `--offset` skips)
+        #[arg(long)]
+        candidates: Option<u64>,
+        /// How many results to keep after reranking
+        #[arg(long)]
+        top_k: Option<usize>,
+        /// Whether results found with quantized vectors are rescored with the original vectors
+        /// (defaults to `search.rescore` in the config)
+        #[arg(long, value_name = "BOOL")]
+        rescore: Option<bool>,
+        /// How many times more results to fetch with quantized vectors before rescoring
+        #[arg(long)]
+        oversampling: Option<f64>,
+        /// How many results (or groups) to skip, to get the next page
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        /// Group results, so one file or type can't take every slot (defaults to
+        /// `search.group_by` in the config)
+        #[arg(long, value_enum)]
+        group_by: Option<Grouping>,
+        /// How many results each group keeps (defaults to `search.group_size` in the config)
+        #[arg(long)]
+        group_size: Option<u64>,
+    },
+
+    /// Find code similar to an indexed symbol or a range of lines
+    Similar {
+        /// A point ID, `file:line_from-line_to` or a symbol name such as `Qdrant::search`
+        seed: Seed,
+        /// The vector to compare
+        #[arg(long, default_value = "code")]
+        vector: String,
+        /// How many results to return (defaults to `search.limit` in the config)
+        #[arg(long)]
+        limit: Option<u64>,
+        /// Where a range of lines is read from if it isn't indexed
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Delete indexed documents by repository, file, directory, crate, symbol or point ID
+    Remove {
+        #[command(flatten)]
+        selection: SelectionArgs,
     },
+
+    /// Delete the indexed code of files that no longer exist
+    Prune {
+        /// The checkout the code was indexed from
+        #[arg(short, long, value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+        /// The repository (`owner/name`) the code was synced as, if it was synced rather than
+        /// embedded
+        #[arg(long)]
+        repo: Option<String>,
+    },
+
+    /// Re-embed the collection with the configured models into a new collection and switch to it
+    Migrate {
+        /// Keep the old collection instead of deleting it once the new one is in use
+        #[arg(long)]
+        keep_old: bool,
+    },
+
+    /// Write every indexed document, with its vectors, to a JSON lines file
+    Export {
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// Store the documents of an export, which must have been embedded with the configured models
+    Import {
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+    },
+
+    /// Back up the Qdrant collection, or restore it from a backup
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
+    },
+
+    /// Count the indexed documents and report duplicates and documents missing payload fields
+    Stats,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// List cached checkouts
+    List,
+    /// Remove cached checkouts
+    Prune {
+        /// Keep this many of the most recently fetched checkouts of each repo
+        #[arg(long)]
+        keep_latest: Option<usize>,
+        /// Only remove checkouts fetched more than this many days ago
+        #[arg(long)]
+        older_than_days: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    /// Snapshot the collection on the Qdrant instance
+    Create {
+        /// Also download the snapshot to this file
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// List the snapshots of the collection, newest first
+    List,
+    /// Download a snapshot of the collection
+    Download {
+        /// Defaults to the newest snapshot
+        name: Option<String>,
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Replace the collection with the contents of a snapshot file
+    Restore {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        /// Keep the replaced collection instead of deleting it
+        #[arg(long)]
+        keep_old: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EmbeddingCacheCommands {
+    /// Show how many embeddings are cached for each model
+    Stats,
+    /// Remove every cached embedding
+    Clear,
 }
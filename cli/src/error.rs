@@ -2,15 +2,21 @@ use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    Parsing,
     Initialise(String),
+    Fetch(String),
+    Cache(String),
+    Index(String),
+    Search(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Parsing => write!(f, "Failed to parse"),
             Self::Initialise(err) => write!(f, "Failed to initialise: {err}"),
+            Self::Fetch(err) => write!(f, "Failed to fetch: {err}"),
+            Self::Cache(err) => write!(f, "Cache error: {err}"),
+            Self::Index(err) => write!(f, "Failed to index: {err}"),
+            Self::Search(err) => write!(f, "Failed to search: {err}"),
         }
     }
 }
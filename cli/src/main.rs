@@ -5,6 +5,7 @@ use clap::Parser;
 use error::Error;
 
 mod error;
+mod sync;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
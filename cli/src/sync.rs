@@ -0,0 +1,212 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use llms::store::{AnyStore, VectorStore};
+use llms::Embedder;
+use parser::archive::FetchOptions;
+use parser::cache::SourceCache;
+use parser::manifest::{FileFilter, Manifest, RepoSource};
+use parser::source::{AnyProvider, SourceProvider};
+
+use crate::args::{ensure_collection, insert_docs, process_dir};
+use crate::error::Error;
+
+/// Syncs every repository in the manifest, carrying on with the rest if one fails.
+pub async fn sync_manifest(
+    manifest: &Manifest,
+    cache: &SourceCache,
+    embedder: &Embedder,
+    store: &AnyStore,
+) -> Result<(), Error> {
+    let mut failed = 0;
+
+    for source in &manifest.repositories {
+        if let Err(err) = sync_repo(source, cache, embedder, store).await {
+            println!("Failed to sync {}: {err}", source.repository());
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::Index(format!(
+            "{failed} repositories failed to sync"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fetches the latest sources of a repository and re-indexes whatever changed since the commit
+/// that is currently indexed.
+///
+/// If the indexed commit is still in the cache only the files that differ are re-embedded,
+/// otherwise all of the repository's code is replaced.
+async fn sync_repo(
+    source: &RepoSource,
+    cache: &SourceCache,
+    embedder: &Embedder,
+    store: &AnyStore,
+) -> Result<(), Error> {
+    let repo = source.repository();
+    let repo_name = repo.to_string();
+    let store = &match &source.namespace {
+        Some(namespace) => store
+            .with_namespace(namespace)
+            .map_err(|x| Error::Initialise(x.to_string()))?,
+        None => store.clone(),
+    };
+    ensure_collection(store, embedder).await?;
+    let filter = source
+        .file_filter()
+        .map_err(|x| Error::Initialise(x.to_string()))?;
+    let provider = AnyProvider::new(source.provider, source.url.clone())
+        .map_err(|x| Error::Initialise(x.to_string()))?;
+
+    println!("Syncing {repo} from {}...", provider.kind());
+    let entry = cache
+        .fetch(
+            &provider,
+            &repo,
+            source.reference.as_deref(),
+            &FetchOptions::default(),
+        )
+        .await
+        .map_err(|x| Error::Fetch(x.to_string()))?;
+
+    let indexed = store
+        .indexed_commit(&repo_name)
+        .await
+        .map_err(|x| Error::Index(x.to_string()))?;
+    if indexed.as_deref() == Some(entry.sha.as_str()) {
+        println!("{repo} is up to date at {}.", entry.sha);
+        return Ok(());
+    }
+
+    let root = entry
+        .source_root()
+        .map_err(|x| Error::Cache(x.to_string()))?;
+    let previous = match &indexed {
+        Some(sha) => cache
+            .get(&provider.cache_key(), &repo, sha)
+            .map_err(|x| Error::Cache(x.to_string()))?,
+        None => None,
+    };
+
+    let items = match previous {
+        Some(previous) => {
+            let previous_root = previous
+                .source_root()
+                .map_err(|x| Error::Cache(x.to_string()))?;
+            let (changed, removed) = diff_files(&previous_root, &root, &filter)
+                .map_err(|x| Error::Cache(x.to_string()))?;
+            println!(
+                "{} files changed and {} removed since {}.",
+                changed.len(),
+                removed.len(),
+                previous.sha
+            );
+
+            let stale: Vec<String> = changed
+                .iter()
+                .chain(&removed)
+                .map(|path| path.to_string_lossy().to_string())
+                .collect();
+            if !stale.is_empty() {
+                store
+                    .delete_code(&repo_name, Some(stale))
+                    .await
+                    .map_err(|x| Error::Index(x.to_string()))?;
+            }
+
+            process_dir(root, &|path| changed.contains(path))?
+        }
+        None => {
+            store
+                .delete_code(&repo_name, None)
+                .await
+                .map_err(|x| Error::Index(x.to_string()))?;
+
+            process_dir(root, &|path| filter.matches(path))?
+        }
+    };
+
+    let items = items
+        .into_iter()
+        .map(|mut item| {
+            if let Some(context) = item.context.as_mut() {
+                context.repository = Some(repo_name.clone());
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+
+    if !items.is_empty() {
+        println!("Embedding {} items...", items.len());
+        let embeddings = embedder
+            .embed_code(items)
+            .map_err(|x| Error::Index(x.to_string()))?;
+        insert_docs(store, embeddings).await?;
+    }
+
+    // only recorded once everything is in, so an interrupted sync is redone next time
+    store
+        .set_commit(&repo_name, &entry.sha)
+        .await
+        .map_err(|x| Error::Index(x.to_string()))?;
+    println!("{repo} is now indexed at {}.", entry.sha);
+
+    Ok(())
+}
+
+/// Files (relative to the roots) that were added or modified, and files that were removed.
+fn diff_files(
+    previous: &Path,
+    current: &Path,
+    filter: &FileFilter,
+) -> io::Result<(HashSet<PathBuf>, HashSet<PathBuf>)> {
+    let previous = hash_files(previous, filter)?;
+    let current = hash_files(current, filter)?;
+
+    let changed = current
+        .iter()
+        .filter(|(path, hash)| previous.get(*path) != Some(hash))
+        .map(|(path, _)| path.clone())
+        .collect();
+    let removed = previous
+        .into_keys()
+        .filter(|path| !current.contains_key(path))
+        .collect();
+
+    Ok((changed, removed))
+}
+
+fn hash_files(root: &Path, filter: &FileFilter) -> io::Result<HashMap<PathBuf, u64>> {
+    let mut hashes = HashMap::new();
+    let mut error = None;
+
+    parser::visit_rs_files(root, &mut |path| {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return;
+        };
+        if !filter.matches(relative) {
+            return;
+        }
+
+        match fs::read(path) {
+            Ok(contents) => {
+                let mut hasher = DefaultHasher::new();
+                contents.hash(&mut hasher);
+                hashes.insert(relative.to_path_buf(), hasher.finish());
+            }
+            Err(err) => error = Some(err),
+        }
+    })?;
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(hashes),
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::EmbeddingCacheConfig;
+
+/// Embeddings on disk, keyed by model and the SHA-256 of the embedded text.
+///
+/// Entries live at `<root>/<model>/<hash[..2]>/<hash>`, each holding the vector as little-endian
+/// `f32`s. They are written to a temporary file and renamed into place, so concurrent runs can
+/// share a cache.
+pub struct EmbeddingCache {
+    root: PathBuf,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Lookups since the cache was opened.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// What's stored for one model.
+#[derive(Debug, Clone)]
+pub struct ModelUsage {
+    pub model: String,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl EmbeddingCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The configured cache, or `None` if it is disabled.
+    pub fn from_config(config: &EmbeddingCacheConfig) -> Option<Self> {
+        config.enabled.then(|| {
+            Self::new(
+                config
+                    .dir
+                    .clone()
+                    .unwrap_or_else(EmbeddingCache::default_dir),
+            )
+        })
+    }
+
+    /// `$DUCKYDUCK_EMBEDDING_CACHE_DIR`, falling back to `duckyduck/embeddings` in the user's
+    /// cache directory.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("DUCKYDUCK_EMBEDDING_CACHE_DIR") {
+            return PathBuf::from(dir);
+        }
+
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from(".cache"))
+            .join("duckyduck")
+            .join("embeddings")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn get(&self, model: &str, text: &str) -> Option<Vec<f32>> {
+        let embedding = fs::read(self.path(model, text)).ok().map(|bytes| {
+            bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect()
+        });
+
+        let counter = match embedding {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        embedding
+    }
+
+    pub fn put(&self, model: &str, text: &str, embedding: &[f32]) -> io::Result<()> {
+        let path = self.path(model, text);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The number of entries and their size on disk, per model.
+    pub fn usage(&self) -> io::Result<Vec<ModelUsage>> {
+        let mut usage = Vec::new();
+        for model in subdirs(&self.root)? {
+            let mut entry = ModelUsage {
+                model: model
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                entries: 0,
+                bytes: 0,
+            };
+
+            for shard in subdirs(&model)? {
+                for file in fs::read_dir(shard)? {
+                    entry.entries += 1;
+                    entry.bytes += file?.metadata()?.len();
+                }
+            }
+            usage.push(entry);
+        }
+
+        Ok(usage)
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) -> io::Result<()> {
+        if self.root.is_dir() {
+            fs::remove_dir_all(&self.root)?;
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, model: &str, text: &str) -> PathBuf {
+        let hash: String = Sha256::digest(text.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        self.root.join(model_dir(model)).join(&hash[..2]).join(hash)
+    }
+}
+
+/// Model names such as `jinaai/jina-embeddings-v2-base-code` as a single directory name.
+fn model_dir(model: &str) -> String {
+    model
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    Ok(dirs)
+}
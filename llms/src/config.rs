@@ -0,0 +1,546 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// The config file looked for in the working directory when none is given.
+pub const DEFAULT_CONFIG_FILE: &str = "duckyduck.toml";
+
+/// Settings shared by the CLI and the server, read from a TOML file such as:
+///
+/// ```toml
+/// [store]
+/// backend = "qdrant"
+///
+/// [qdrant]
+/// url = "https://qdrant.internal:6334"
+/// rest_url = "https://qdrant.internal:6333"
+/// collection = "code"
+/// namespace = "platform"
+/// namespace_mode = "tenant"
+///
+/// [qdrant.upsert]
+/// batch_size = 128
+/// concurrency = 8
+///
+/// [qdrant.index]
+/// m = 32
+/// ef_construct = 200
+/// vectors_on_disk = true
+///
+/// [qdrant.index.quantization]
+/// method = "scalar"
+/// quantile = 0.99
+/// always_ram = true
+///
+/// [models]
+/// dir = "/opt/models"
+/// offline = true
+///
+/// [search]
+/// fusion = "weighted"
+/// weights = { code = 1.0, nlp = 0.5, lexical = 0.8 }
+/// oversampling = 2.0
+/// group_by = "file"
+/// group_size = 2
+///
+/// [rerank]
+/// enabled = true
+/// candidates = 30
+/// top_k = 8
+///
+/// [embedding_cache]
+/// dir = "/var/cache/duckyduck/embeddings"
+///
+/// [templates]
+/// version = 2
+///
+/// [templates.vectors.nlp]
+/// code = "{kind} {qualified_name}\n{docs}"
+///
+/// [embeddings.code]
+/// provider = "hf_hub"
+/// repo = "jinaai/jina-embeddings-v2-base-code"
+/// onnx_file = "onnx/model_quantized.onnx"
+///
+/// [embeddings.nlp]
+/// provider = "fastembed"
+/// model = "BGESmallENV15"
+///
+/// [embeddings.text]
+/// provider = "fastembed"
+/// model = "intfloat/multilingual-e5-base"
+/// passage_prefix = "passage: "
+///
+/// [embeddings.docs]
+/// provider = "open_ai"
+/// url = "http://localhost:8080/v1"
+/// model = "nomic-embed-text"
+/// batch_size = 64
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub store: StoreConfig,
+    #[serde(default)]
+    pub qdrant: QdrantConfig,
+    #[serde(default)]
+    pub models: ModelsConfig,
+    #[serde(default)]
+    pub embedding_cache: EmbeddingCacheConfig,
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    #[serde(default)]
+    pub sparse: SparseConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub rerank: RerankConfig,
+    /// The embedding model behind each named vector.
+    #[serde(default = "default_embeddings")]
+    pub embeddings: BTreeMap<String, EmbeddingConfig>,
+}
+
+/// Where documents are stored, see [`crate::store::AnyStore`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    pub backend: StoreBackend,
+    /// The directory the local backend keeps a file per collection in, `.duckyduck` in the
+    /// working directory by default.
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// The Qdrant instance under `[qdrant]`.
+    #[default]
+    Qdrant,
+    /// An in-process store saved to a file, needing no other services. The collection and
+    /// namespace settings under `[qdrant]` still apply.
+    Local,
+}
+
+/// The Qdrant instance and collection documents are stored in.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QdrantConfig {
+    /// The gRPC endpoint. `https` URLs are connected to over TLS.
+    pub url: String,
+    /// Falls back to `$QDRANT_API_KEY`.
+    pub api_key: Option<String>,
+    /// Use TLS even though the URL is `http`.
+    pub tls: bool,
+    /// The REST endpoint, which snapshots are downloaded from and uploaded to. Defaults to `url`
+    /// with port 6334 replaced by 6333.
+    pub rest_url: Option<String>,
+    pub collection: String,
+    /// Keeps the documents of a project apart from others sharing the same instance.
+    pub namespace: Option<String>,
+    pub namespace_mode: NamespaceMode,
+    pub upsert: UpsertConfig,
+    pub index: IndexConfig,
+}
+
+/// How documents are written, see [`crate::store::VectorStore::insert_docs`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UpsertConfig {
+    /// How many points are sent in one request.
+    pub batch_size: usize,
+    /// How many requests are in flight at once.
+    pub concurrency: usize,
+    /// How many times a batch is retried after a transient failure, such as a timeout.
+    pub retries: u32,
+    /// How long to wait before the first retry, doubling with every attempt.
+    pub backoff_ms: u64,
+}
+
+/// How collections are indexed when they are created, trading memory for accuracy. Changes only
+/// apply to collections created afterwards, e.g. by `migrate`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// Edges per node of the HNSW graph: more is more accurate and uses more memory.
+    pub m: Option<u64>,
+    /// Neighbours considered when building the HNSW graph: more is more accurate and slower to
+    /// index.
+    pub ef_construct: Option<u64>,
+    /// Keep the HNSW graph on disk instead of in memory.
+    pub hnsw_on_disk: bool,
+    /// Keep the original vectors on disk, leaving only the quantized vectors in memory.
+    pub vectors_on_disk: bool,
+    pub quantization: Option<Quantization>,
+}
+
+/// How vectors are compressed in memory. Searches then compare compressed vectors, and rescore
+/// the best of them with the originals unless [`SearchConfig::rescore`] is off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Quantization {
+    /// A byte per dimension, 4 times smaller.
+    Scalar {
+        /// The share of values the int8 range is fitted to, leaving out outliers.
+        #[serde(default)]
+        quantile: Option<f32>,
+        #[serde(default)]
+        always_ram: bool,
+    },
+    /// Groups of dimensions replaced by their nearest centroid.
+    Product {
+        #[serde(default)]
+        compression: Compression,
+        #[serde(default)]
+        always_ram: bool,
+    },
+    /// A bit per dimension, 32 times smaller, which only suits models with many dimensions.
+    Binary {
+        #[serde(default)]
+        always_ram: bool,
+    },
+}
+
+/// How many times smaller product quantization makes vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    X4,
+    X8,
+    #[default]
+    X16,
+    X32,
+    X64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamespaceMode {
+    /// A collection per namespace, named `<collection>_<namespace>`.
+    #[default]
+    Collection,
+    /// One collection, with the namespace stored on every point and filtered on by every request.
+    Tenant,
+}
+
+/// Where models are loaded from.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelsConfig {
+    /// A directory of models, either a Hugging Face hub cache or plain files under
+    /// `<org>/<name>/`. Models found here are used without going to the hub.
+    pub dir: Option<PathBuf>,
+    /// Never download models, failing if one isn't in `dir`.
+    #[serde(default)]
+    pub offline: bool,
+}
+
+/// Where computed embeddings are kept, see [`crate::cache::EmbeddingCache`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingCacheConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Defaults to `$DUCKYDUCK_EMBEDDING_CACHE_DIR` or the user's cache directory.
+    pub dir: Option<PathBuf>,
+}
+
+/// The BM25 parameters of the lexical sparse vector, see [`crate::sparse::SparseEncoder`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SparseConfig {
+    pub enabled: bool,
+    /// How quickly repeated terms stop adding weight.
+    pub k1: f32,
+    /// How much longer documents are penalised, from 0 to 1, relative to the average length of
+    /// the documents in the collection.
+    pub b: f32,
+}
+
+/// How the results of each vector are combined, see [`crate::store::SearchOptions`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// How many results are returned.
+    pub limit: u64,
+    pub fusion: Fusion,
+    /// The `k` of reciprocal rank fusion: higher values flatten the gap between ranks.
+    pub rrf_k: f32,
+    /// How much each vector counts towards the fused score, by name. Missing vectors count 1.
+    pub weights: BTreeMap<String, f32>,
+    /// Whether results found with quantized vectors are rescored with the original vectors.
+    /// Qdrant rescores by default.
+    pub rescore: Option<bool>,
+    /// How many times more results to fetch with quantized vectors before rescoring them.
+    pub oversampling: Option<f64>,
+    /// Groups results so that one file or type can't fill every slot.
+    pub group_by: Option<GroupBy>,
+    /// How many results each group keeps.
+    pub group_size: u64,
+}
+
+/// What search results are grouped by, see [`crate::store::SearchOptions::group_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    /// The file a document is in, or the first file a discussion mentions.
+    File,
+    /// The type a method is implemented on, or the pull request a review comment was left on.
+    /// Other documents are groups of their own.
+    Parent,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fusion {
+    /// Reciprocal rank fusion, which only looks at the rank of a point in each vector's results.
+    #[default]
+    Rrf,
+    /// The weighted sum of each vector's scores, scaled so its best result scores 1.
+    Weighted,
+}
+
+/// The cross-encoder used to rerank search results, see [`crate::rerank::Reranker`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RerankConfig {
+    pub enabled: bool,
+    /// A Hugging Face repository with an ONNX cross-encoder and its `tokenizer.json`.
+    pub repo: String,
+    pub revision: String,
+    pub onnx_file: String,
+    /// Tokens of prompt and document a pair is truncated to.
+    pub max_length: usize,
+    /// How many search results are reranked.
+    pub candidates: u64,
+    /// How many results are kept after reranking.
+    pub top_k: usize,
+}
+
+/// How documents are rendered to text before being embedded, see [`crate::render::Renderer`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplatesConfig {
+    /// Should be bumped whenever the templates change.
+    #[serde(default = "default_template_version")]
+    pub version: u32,
+    /// Templates by vector name, then by document kind.
+    #[serde(default)]
+    pub vectors: BTreeMap<String, HashMap<String, String>>,
+}
+
+/// The model behind one named vector.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbeddingConfig {
+    #[serde(flatten)]
+    pub backend: EmbeddingBackend,
+    /// Put in front of search queries, overriding what the model declares (e.g. `query: `).
+    pub query_prefix: Option<String>,
+    /// Put in front of indexed documents, overriding what the model declares (e.g. `passage: `).
+    pub passage_prefix: Option<String>,
+    /// A separate encoder for queries, for models with distinct query and document encoders.
+    pub query: Option<EmbeddingBackend>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum EmbeddingBackend {
+    /// A model supported by fastembed, by name (`AllMiniLML6V2`) or repository
+    /// (`Qdrant/all-MiniLM-L6-v2-onnx`).
+    Fastembed { model: String },
+    /// An ONNX model and its tokenizer files from a Hugging Face hub repository.
+    HfHub {
+        repo: String,
+        #[serde(default = "default_revision")]
+        revision: String,
+        #[serde(default = "default_onnx_file")]
+        onnx_file: String,
+    },
+    /// An ONNX model and its tokenizer files in a local directory.
+    Onnx {
+        dir: PathBuf,
+        #[serde(default = "default_onnx_file")]
+        onnx_file: String,
+    },
+    /// An OpenAI-compatible embeddings endpoint. The dimension is asked for with a test request
+    /// if not given.
+    OpenAi {
+        url: String,
+        model: String,
+        dimension: Option<usize>,
+        /// The environment variable holding the API key, if one is needed.
+        api_key_env: Option<String>,
+        /// How many texts are sent in one request, since endpoints limit the inputs per request.
+        #[serde(default = "default_openai_batch_size")]
+        batch_size: usize,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            store: StoreConfig::default(),
+            qdrant: QdrantConfig::default(),
+            models: ModelsConfig::default(),
+            embedding_cache: EmbeddingCacheConfig::default(),
+            templates: TemplatesConfig::default(),
+            sparse: SparseConfig::default(),
+            search: SearchConfig::default(),
+            rerank: RerankConfig::default(),
+            embeddings: default_embeddings(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config from {}", path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Reads `path` if given, otherwise `duckyduck.toml` if it exists, otherwise uses the defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_path(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Self::from_path(DEFAULT_CONFIG_FILE)
+            }
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+impl EmbeddingConfig {
+    pub fn new(backend: EmbeddingBackend) -> Self {
+        Self {
+            backend,
+            query_prefix: None,
+            passage_prefix: None,
+            query: None,
+        }
+    }
+}
+
+impl Default for EmbeddingCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+        }
+    }
+}
+
+impl Default for SparseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            k1: 1.2,
+            b: 0.75,
+        }
+    }
+}
+
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:6334".to_string(),
+            api_key: None,
+            tls: false,
+            rest_url: None,
+            collection: crate::qdrant::COLLECTION_NAME.to_string(),
+            namespace: None,
+            namespace_mode: NamespaceMode::default(),
+            upsert: UpsertConfig::default(),
+            index: IndexConfig::default(),
+        }
+    }
+}
+
+impl Default for UpsertConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            concurrency: 4,
+            retries: 3,
+            backoff_ms: 500,
+        }
+    }
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            limit: 4,
+            fusion: Fusion::default(),
+            rrf_k: 60.0,
+            weights: BTreeMap::new(),
+            rescore: None,
+            oversampling: None,
+            group_by: None,
+            group_size: 3,
+        }
+    }
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            repo: "BAAI/bge-reranker-base".to_string(),
+            revision: default_revision(),
+            onnx_file: "onnx/model.onnx".to_string(),
+            max_length: 512,
+            candidates: 20,
+            top_k: 5,
+        }
+    }
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        Self {
+            version: default_template_version(),
+            vectors: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_template_version() -> u32 {
+    1
+}
+
+fn default_embeddings() -> BTreeMap<String, EmbeddingConfig> {
+    BTreeMap::from([
+        (
+            "code".to_string(),
+            EmbeddingConfig::new(EmbeddingBackend::HfHub {
+                repo: "jinaai/jina-embeddings-v2-base-code".to_string(),
+                revision: default_revision(),
+                onnx_file: "onnx/model_quantized.onnx".to_string(),
+            }),
+        ),
+        (
+            "nlp".to_string(),
+            EmbeddingConfig::new(EmbeddingBackend::Fastembed {
+                model: "AllMiniLML6V2".to_string(),
+            }),
+        ),
+    ])
+}
+
+fn default_revision() -> String {
+    "main".to_string()
+}
+
+fn default_onnx_file() -> String {
+    "model.onnx".to_string()
+}
+
+fn default_openai_batch_size() -> usize {
+    128
+}
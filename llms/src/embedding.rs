@@ -0,0 +1,439 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use fastembed::{
+    EmbeddingModel, InitOptionsUserDefined, TextEmbedding, TokenizerFiles,
+    UserDefinedEmbeddingModel,
+};
+use serde::Deserialize;
+
+use crate::config::{EmbeddingBackend, EmbeddingConfig};
+use crate::models::{ModelFiles, ModelRepo};
+
+/// A model turning text into fixed-size vectors.
+pub trait EmbeddingProvider: Send + Sync {
+    fn model_name(&self) -> &str;
+
+    /// The length of the vectors returned by `embed`.
+    fn dimension(&self) -> usize;
+
+    /// What the model expects in front of queries and passages, for models trained with
+    /// instructions or prefixes. None by default.
+    fn prefixes(&self) -> Prefixes {
+        Prefixes::default()
+    }
+
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+}
+
+/// Whether text is a search query or a document being indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Query,
+    Passage,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Prefixes {
+    pub query: Option<String>,
+    pub passage: Option<String>,
+}
+
+impl Prefixes {
+    fn new(query: &str, passage: &str) -> Self {
+        let prefix = |prefix: &str| (!prefix.is_empty()).then(|| prefix.to_string());
+
+        Self {
+            query: prefix(query),
+            passage: prefix(passage),
+        }
+    }
+
+    pub fn apply(&self, kind: InputKind, text: &str) -> String {
+        let prefix = match kind {
+            InputKind::Query => &self.query,
+            InputKind::Passage => &self.passage,
+        };
+
+        match prefix {
+            Some(prefix) => format!("{prefix}{text}"),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// The encoder(s) filling one named vector, along with how text is formatted for them.
+pub struct VectorModel {
+    passage: Box<dyn EmbeddingProvider>,
+    query: Option<Box<dyn EmbeddingProvider>>,
+    prefixes: Prefixes,
+}
+
+impl VectorModel {
+    /// Creates the model described by `config`, loading any model files through `files`.
+    pub fn from_config(config: &EmbeddingConfig, files: &ModelFiles) -> Result<Self> {
+        let passage = from_config(&config.backend, files)?;
+        let query = match &config.query {
+            Some(backend) => {
+                let query = from_config(backend, files)?;
+                if query.dimension() != passage.dimension() {
+                    bail!(
+                        "the query encoder {} has {} dimensions, but {} has {}",
+                        query.model_name(),
+                        query.dimension(),
+                        passage.model_name(),
+                        passage.dimension()
+                    );
+                }
+                Some(query)
+            }
+            None => None,
+        };
+
+        let declared = passage.prefixes();
+        let prefixes = Prefixes {
+            query: config.query_prefix.clone().or(declared.query),
+            passage: config.passage_prefix.clone().or(declared.passage),
+        };
+
+        Ok(Self {
+            passage,
+            query,
+            prefixes,
+        })
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.passage.dimension()
+    }
+
+    /// The name of the model documents are embedded with, and of the query encoder if separate.
+    pub fn model_names(&self) -> (&str, Option<&str>) {
+        (
+            self.passage.model_name(),
+            self.query.as_ref().map(|query| query.model_name()),
+        )
+    }
+
+    pub fn encoder(&self, kind: InputKind) -> &dyn EmbeddingProvider {
+        match (kind, &self.query) {
+            (InputKind::Query, Some(query)) => query.as_ref(),
+            _ => self.passage.as_ref(),
+        }
+    }
+
+    pub fn format(&self, kind: InputKind, text: &str) -> String {
+        self.prefixes.apply(kind, text)
+    }
+}
+
+fn from_config(
+    config: &EmbeddingBackend,
+    files: &ModelFiles,
+) -> Result<Box<dyn EmbeddingProvider>> {
+    let provider: Box<dyn EmbeddingProvider> = match config {
+        EmbeddingBackend::Fastembed { model } => Box::new(FastEmbed::builtin(model, files)?),
+        EmbeddingBackend::HfHub {
+            repo,
+            revision,
+            onnx_file,
+        } => Box::new(FastEmbed::hf_hub(&files.repo(repo, revision), onnx_file)?),
+        EmbeddingBackend::Onnx { dir, onnx_file } => Box::new(FastEmbed::local(dir, onnx_file)?),
+        EmbeddingBackend::OpenAi {
+            url,
+            model,
+            dimension,
+            api_key_env,
+            batch_size,
+        } => {
+            let api_key = match api_key_env {
+                Some(var) => Some(
+                    std::env::var(var)
+                        .with_context(|| format!("reading the API key from {var}"))?,
+                ),
+                None => None,
+            };
+            Box::new(OpenAiEmbeddings::new(
+                url,
+                model,
+                *dimension,
+                *batch_size,
+                api_key,
+            )?)
+        }
+    };
+
+    Ok(provider)
+}
+
+/// A model run locally through fastembed: either one it supports out of the box or any ONNX
+/// model with a Hugging Face tokenizer.
+pub struct FastEmbed {
+    name: String,
+    dimension: usize,
+    prefixes: Prefixes,
+    model: TextEmbedding,
+}
+
+impl FastEmbed {
+    pub fn builtin(name: &str, files: &ModelFiles) -> Result<Self> {
+        let info = TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|info| {
+                info.model_code.eq_ignore_ascii_case(name)
+                    || format!("{:?}", info.model).eq_ignore_ascii_case(name)
+            })
+            .ok_or_else(|| anyhow!("{name} is not a model supported by fastembed"))?;
+
+        // loaded like any other model so that it can come from the model directory
+        let repo = files.repo(&info.model_code, "main");
+        let files = TokenizerFilePaths::new(|file| repo.get(file), &info.model_file)?;
+        let model = TextEmbedding::try_new_from_user_defined(
+            files.into_model()?,
+            InitOptionsUserDefined::default(),
+        )?;
+
+        Ok(Self {
+            prefixes: builtin_prefixes(&info.model),
+            name: info.model_code,
+            dimension: info.dim,
+            model,
+        })
+    }
+
+    pub fn hf_hub(repo: &ModelRepo, onnx_file: &str) -> Result<Self> {
+        let files = TokenizerFilePaths::new(|file| repo.get(file), onnx_file)?;
+
+        // the revision and the ONNX file are part of the name, since changing either changes the
+        // vectors and the name is what cached vectors and the indexed schema are keyed on
+        let name = format!("{}@{}/{onnx_file}", repo.id(), repo.revision());
+        Self::user_defined(name, files.into_model()?)
+    }
+
+    pub fn local(dir: &Path, onnx_file: &str) -> Result<Self> {
+        let files = TokenizerFilePaths::new(
+            |file| {
+                let path = dir.join(file);
+                if !path.is_file() {
+                    bail!("{} does not exist", path.display());
+                }
+                Ok(path)
+            },
+            onnx_file,
+        )?;
+
+        Self::user_defined(
+            dir.join(onnx_file).display().to_string(),
+            files.into_model()?,
+        )
+    }
+
+    fn user_defined(name: String, model: UserDefinedEmbeddingModel) -> Result<Self> {
+        let model =
+            TextEmbedding::try_new_from_user_defined(model, InitOptionsUserDefined::default())?;
+
+        // there's no metadata to read the dimension from, so ask the model itself
+        let dimension = model
+            .embed(vec!["dimension"], None)?
+            .first()
+            .map(Vec::len)
+            .ok_or_else(|| anyhow!("{name} returned no embedding"))?;
+
+        Ok(Self {
+            name,
+            dimension,
+            prefixes: Prefixes::default(),
+            model,
+        })
+    }
+}
+
+impl EmbeddingProvider for FastEmbed {
+    fn model_name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn prefixes(&self) -> Prefixes {
+        self.prefixes.clone()
+    }
+
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.model.embed(texts, None)
+    }
+}
+
+/// The prefixes the fastembed models were trained with, from their model cards.
+fn builtin_prefixes(model: &EmbeddingModel) -> Prefixes {
+    const BGE_QUERY: &str = "Represent this sentence for searching relevant passages: ";
+
+    match model {
+        EmbeddingModel::MultilingualE5Small
+        | EmbeddingModel::MultilingualE5Base
+        | EmbeddingModel::MultilingualE5Large => Prefixes::new("query: ", "passage: "),
+        EmbeddingModel::NomicEmbedTextV1
+        | EmbeddingModel::NomicEmbedTextV15
+        | EmbeddingModel::NomicEmbedTextV15Q => {
+            Prefixes::new("search_query: ", "search_document: ")
+        }
+        EmbeddingModel::BGEBaseENV15
+        | EmbeddingModel::BGEBaseENV15Q
+        | EmbeddingModel::BGELargeENV15
+        | EmbeddingModel::BGELargeENV15Q
+        | EmbeddingModel::BGESmallENV15
+        | EmbeddingModel::BGESmallENV15Q
+        | EmbeddingModel::MxbaiEmbedLargeV1
+        | EmbeddingModel::MxbaiEmbedLargeV1Q => Prefixes::new(BGE_QUERY, ""),
+        EmbeddingModel::BGESmallZHV15 => {
+            Prefixes::new("为这个句子生成表示以用于检索相关文章：", "")
+        }
+        _ => Prefixes::default(),
+    }
+}
+
+/// Any server implementing OpenAI's `POST /embeddings`, e.g. OpenAI itself, vLLM, Ollama or
+/// text-embeddings-inference.
+pub struct OpenAiEmbeddings {
+    agent: ureq::Agent,
+    url: String,
+    model: String,
+    /// The model and the endpoint serving it, as the same model name can mean different models
+    /// on different servers.
+    name: String,
+    api_key: Option<String>,
+    dimension: usize,
+    batch_size: usize,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbeddings {
+    /// `url` is the API base, e.g. `https://api.openai.com/v1`.
+    pub fn new(
+        url: &str,
+        model: &str,
+        dimension: Option<usize>,
+        batch_size: usize,
+        api_key: Option<String>,
+    ) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        let mut provider = Self {
+            agent: ureq::Agent::new(),
+            url: format!("{url}/embeddings"),
+            model: model.to_string(),
+            name: format!("{model}@{url}"),
+            api_key,
+            dimension: dimension.unwrap_or_default(),
+            batch_size: batch_size.max(1),
+        };
+
+        if dimension.is_none() {
+            provider.dimension = provider
+                .embed(vec!["dimension".to_string()])?
+                .first()
+                .map(Vec::len)
+                .ok_or_else(|| anyhow!("{} returned no embedding", provider.url))?;
+        }
+
+        Ok(provider)
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let count = texts.len();
+        let mut req = self.agent.post(&self.url);
+        if let Some(api_key) = &self.api_key {
+            req = req.set("Authorization", &format!("Bearer {api_key}"));
+        }
+
+        let mut res: EmbeddingsResponse = req
+            .send_json(serde_json::json!({ "model": self.model, "input": texts }))
+            .with_context(|| format!("requesting embeddings from {}", self.url))?
+            .into_json()?;
+
+        if res.data.len() != count {
+            bail!(
+                "{} returned {} embeddings for {count} inputs",
+                self.url,
+                res.data.len()
+            );
+        }
+        res.data.sort_by_key(|data| data.index);
+
+        Ok(res.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+impl EmbeddingProvider for OpenAiEmbeddings {
+    fn model_name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Sends the texts `batch_size` at a time, keeping the embeddings in the order of `texts`.
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            embeddings.extend(self.embed_batch(batch)?);
+        }
+
+        Ok(embeddings)
+    }
+}
+
+struct TokenizerFilePaths {
+    onnx_file: PathBuf,
+    tokenizer_file: PathBuf,
+    tokenizer_config_file: PathBuf,
+    config_file: PathBuf,
+    special_tokens_map_file: PathBuf,
+}
+
+impl TokenizerFilePaths {
+    fn new(get: impl Fn(&str) -> Result<PathBuf>, onnx_file: &str) -> Result<Self> {
+        Ok(Self {
+            onnx_file: get(onnx_file)?,
+            tokenizer_file: get("tokenizer.json")?,
+            tokenizer_config_file: get("tokenizer_config.json")?,
+            config_file: get("config.json")?,
+            special_tokens_map_file: get("special_tokens_map.json")?,
+        })
+    }
+
+    fn into_model(self) -> Result<UserDefinedEmbeddingModel> {
+        let read = |path: PathBuf| {
+            std::fs::read(&path).with_context(|| format!("reading {}", path.display()))
+        };
+        let onnx_file = read(self.onnx_file)?;
+        let tokenizer_file = read(self.tokenizer_file)?;
+        let tokenizer_config_file = read(self.tokenizer_config_file)?;
+        let config_file = read(self.config_file)?;
+        let special_tokens_map_file = read(self.special_tokens_map_file)?;
+
+        let tokenizer_files = TokenizerFiles {
+            tokenizer_file,
+            tokenizer_config_file,
+            config_file,
+            special_tokens_map_file,
+        };
+
+        Ok(UserDefinedEmbeddingModel {
+            onnx_file,
+            tokenizer_files,
+        })
+    }
+}
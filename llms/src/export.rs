@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use qdrant_client::qdrant::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::Schema;
+use crate::sparse::SparseVector;
+use crate::store::VectorStore;
+
+/// Identifies the first line of an export.
+pub const EXPORT_FORMAT: &str = "duckyduck-export";
+
+/// The version of the export layout, bumped whenever older readers can't read it.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// The first line of an export, describing the points on the lines after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub format: String,
+    pub version: u32,
+    /// The collection the points were exported from.
+    pub collection: String,
+    /// What the points were indexed with.
+    pub schema: Schema,
+    /// When the export was made, in seconds since the Unix epoch.
+    pub exported_at: i64,
+}
+
+/// A point as it is exported, without the namespace it was stored under.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedPoint {
+    pub id: String,
+    pub vectors: HashMap<String, Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<SparseVector>,
+    pub payload: HashMap<String, Value>,
+}
+
+/// Writes every point of the store's namespace to `writer` as JSON lines, after a header with the
+/// collection's schema, returning how many points were written.
+pub async fn export(store: &impl VectorStore, writer: impl Write) -> Result<u64> {
+    let schema = store.indexed_schema().await?.ok_or_else(|| {
+        anyhow!(
+            "collection {} has no schema to export; run `migrate` to record one",
+            store.collection()
+        )
+    })?;
+
+    let header = ExportHeader {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        collection: store.collection().to_string(),
+        schema,
+        exported_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+    };
+
+    let mut writer = BufWriter::new(writer);
+    write_line(&mut writer, &header)?;
+
+    let mut points = 0;
+    store
+        .export_points(|point| {
+            write_line(&mut writer, &point)?;
+            points += 1;
+            Ok(())
+        })
+        .await?;
+    writer.flush()?;
+
+    Ok(points)
+}
+
+/// Stores the points of an export in batches of `batch_size`, keeping their IDs so importing the
+/// same export twice replaces the points instead of duplicating them. Returns how many points
+/// were imported.
+pub async fn import<R: BufRead>(
+    store: &impl VectorStore,
+    reader: ExportReader<R>,
+    batch_size: usize,
+    progress: impl Fn(usize),
+) -> Result<usize> {
+    let batch_size = batch_size.max(1);
+    let mut imported = 0;
+    let mut batch = Vec::with_capacity(batch_size);
+
+    for point in reader {
+        batch.push(point?);
+        if batch.len() == batch_size {
+            imported += batch.len();
+            store.import_points(std::mem::take(&mut batch)).await?;
+            progress(imported);
+        }
+    }
+
+    if !batch.is_empty() {
+        imported += batch.len();
+        store.import_points(batch).await?;
+        progress(imported);
+    }
+
+    Ok(imported)
+}
+
+/// Reads the points of an export one line at a time, checking that each has the vectors its
+/// header promises.
+pub struct ExportReader<R> {
+    header: ExportHeader,
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> ExportReader<R> {
+    /// Reads the header of an export.
+    pub fn new(reader: R) -> Result<Self> {
+        let mut lines = reader.lines();
+        let first = lines
+            .next()
+            .ok_or_else(|| anyhow!("the export is empty"))??;
+
+        let header: ExportHeader =
+            serde_json::from_str(&first).context("the export has no header")?;
+        if header.format != EXPORT_FORMAT {
+            bail!("not an export: the format is `{}`", header.format);
+        }
+        if header.version > EXPORT_VERSION {
+            bail!(
+                "the export has version {}, but only versions up to {EXPORT_VERSION} can be read",
+                header.version
+            );
+        }
+
+        Ok(Self {
+            header,
+            lines,
+            line: 1,
+        })
+    }
+
+    pub fn header(&self) -> &ExportHeader {
+        &self.header
+    }
+
+    /// Fails if the points were embedded with other models than those of `current`, since
+    /// searching them would then return nonsense.
+    pub fn check(&self, current: &Schema) -> Result<()> {
+        let conflicts = self.header.schema.conflicts(current);
+        if !conflicts.is_empty() {
+            bail!(
+                "the export doesn't match the configured models: {}",
+                conflicts.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    fn read_point(&self, line: &str) -> Result<ExportedPoint> {
+        let point: ExportedPoint = serde_json::from_str(line)?;
+
+        for (name, vector) in &self.header.schema.vectors {
+            let dimension = point
+                .vectors
+                .get(name)
+                .ok_or_else(|| anyhow!("point {} has no `{name}` vector", point.id))?
+                .len();
+            if dimension != vector.dimension {
+                bail!(
+                    "the `{name}` vector of point {} has {dimension} dimensions instead of {}",
+                    point.id,
+                    vector.dimension
+                );
+            }
+        }
+
+        Ok(point)
+    }
+}
+
+impl<R: BufRead> Iterator for ExportReader<R> {
+    type Item = Result<ExportedPoint>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+
+            if !line.trim().is_empty() {
+                let line_number = self.line;
+                return Some(
+                    self.read_point(&line)
+                        .with_context(|| format!("line {line_number} of the export")),
+                );
+            }
+        }
+    }
+}
+
+fn write_line(writer: &mut impl Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::schema::{VectorSchema, SCHEMA_VERSION};
+
+    fn header(version: u32) -> String {
+        let schema = Schema {
+            version: SCHEMA_VERSION,
+            vectors: BTreeMap::from([(
+                "code".to_string(),
+                VectorSchema {
+                    model: "test-model".to_string(),
+                    query_model: None,
+                    dimension: 2,
+                },
+            )]),
+            sparse: false,
+            template_version: 1,
+        };
+
+        serde_json::to_string(&ExportHeader {
+            format: EXPORT_FORMAT.to_string(),
+            version,
+            collection: "test".to_string(),
+            schema,
+            exported_at: 0,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_points_with_the_promised_vectors() {
+        let export = format!(
+            "{}\n{{\"id\":\"a\",\"vectors\":{{\"code\":[0.1,0.2]}},\"payload\":{{}}}}\n",
+            header(EXPORT_VERSION)
+        );
+
+        let points = ExportReader::new(export.as_bytes())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].id, "a");
+    }
+
+    #[test]
+    fn rejects_points_with_the_wrong_dimension() {
+        let export = format!(
+            "{}\n{{\"id\":\"a\",\"vectors\":{{\"code\":[0.1,0.2,0.3]}},\"payload\":{{}}}}\n",
+            header(EXPORT_VERSION)
+        );
+
+        let err = ExportReader::new(export.as_bytes())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("has 3 dimensions instead of 2"));
+    }
+
+    #[test]
+    fn rejects_newer_exports() {
+        let export = format!("{}\n", header(EXPORT_VERSION + 1));
+
+        let err = ExportReader::new(export.as_bytes()).err().unwrap();
+        assert!(err.to_string().contains("only versions up to"));
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+use parser::discussion::{symbol_name, TDiscussion};
+use parser::{CodeType, Document, DocumentKind, TCode, Visibility};
+use qdrant_client::qdrant::{Condition, FieldType, Filter, Value};
+use serde::Deserialize;
+
+use crate::config::GroupBy;
+use crate::similar::point_id;
+
+/// The payload keys searches can be filtered on, and how each is indexed.
+pub const INDEXED_FIELDS: &[(&str, FieldType)] = &[
+    ("kind", FieldType::Keyword),
+    ("repo", FieldType::Keyword),
+    ("code_type", FieldType::Keyword),
+    ("symbol", FieldType::Keyword),
+    ("crate", FieldType::Keyword),
+    ("modules", FieldType::Keyword),
+    ("files", FieldType::Keyword),
+    ("dirs", FieldType::Keyword),
+    ("visibility", FieldType::Keyword),
+    ("test", FieldType::Bool),
+    ("parent", FieldType::Keyword),
+];
+
+/// Limits a search to documents matching every field that is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchFilter {
+    /// Any of these kinds of document.
+    pub kinds: Vec<DocumentKind>,
+    /// Any of these kinds of code.
+    pub code_types: Vec<CodeType>,
+    #[serde(rename = "crate")]
+    pub crate_name: Option<String>,
+    /// A module path, matching the module and everything below it.
+    pub module: Option<String>,
+    /// A glob of file paths relative to the repository root. Discussions match if they mention a
+    /// matching file.
+    pub file: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub repo: Option<String>,
+    /// Only tests and test-only code, or none of it.
+    pub test: Option<bool>,
+}
+
+impl SearchFilter {
+    /// The payload filter to search with.
+    ///
+    /// Qdrant can't match globs, so a `file` glob is only narrowed down to the directory before
+    /// its first wildcard; [`SearchFilter::file_matcher`] checks the rest on the results.
+    pub fn to_qdrant(&self) -> Option<Filter> {
+        let mut must = Vec::new();
+        let mut must_not = Vec::new();
+
+        if !self.kinds.is_empty() {
+            let kinds: Vec<String> = self.kinds.iter().map(ToString::to_string).collect();
+            must.push(Condition::matches("kind", kinds));
+        }
+        if !self.code_types.is_empty() {
+            let types: Vec<String> = self.code_types.iter().map(ToString::to_string).collect();
+            must.push(Condition::matches("code_type", types));
+        }
+        if let Some(crate_name) = &self.crate_name {
+            must.push(Condition::matches("crate", crate_name.replace('-', "_")));
+        }
+        if let Some(module) = &self.module {
+            must.push(Condition::matches("modules", module.clone()));
+        }
+        if let Some(file) = &self.file {
+            match glob_dir(file) {
+                None => must.push(Condition::matches("files", file.clone())),
+                Some(dir) if !dir.is_empty() => must.push(Condition::matches("dirs", dir)),
+                Some(_) => {}
+            }
+        }
+        if let Some(visibility) = self.visibility {
+            must.push(Condition::matches("visibility", visibility.to_string()));
+        }
+        if let Some(repo) = &self.repo {
+            must.push(Condition::matches("repo", repo.clone()));
+        }
+        // discussions aren't tests either, so non-test means "not marked as a test"
+        match self.test {
+            Some(true) => must.push(Condition::matches("test", true)),
+            Some(false) => must_not.push(Condition::matches("test", true)),
+            None => {}
+        }
+
+        if must.is_empty() && must_not.is_empty() {
+            return None;
+        }
+
+        Some(Filter {
+            must,
+            must_not,
+            ..Default::default()
+        })
+    }
+
+    /// Whether a point's payload matches every field but the `file` glob, the way Qdrant would
+    /// apply [`SearchFilter::to_qdrant`].
+    pub(crate) fn matches(&self, payload: &HashMap<String, Value>) -> bool {
+        let any = |key, values: Vec<String>| {
+            values.is_empty() || values.iter().any(|value| has_keyword(payload, key, value))
+        };
+        let is = |key, value: Option<String>| {
+            value.is_none_or(|value| has_keyword(payload, key, &value))
+        };
+
+        let test = payload.get("test").and_then(Value::as_bool) == Some(true);
+
+        any("kind", self.kinds.iter().map(ToString::to_string).collect())
+            && any(
+                "code_type",
+                self.code_types.iter().map(ToString::to_string).collect(),
+            )
+            && is(
+                "crate",
+                self.crate_name.as_ref().map(|x| x.replace('-', "_")),
+            )
+            && is("modules", self.module.clone())
+            && is("visibility", self.visibility.map(|x| x.to_string()))
+            && is("repo", self.repo.clone())
+            && self.test.is_none_or(|only| only == test)
+    }
+
+    /// The `file` glob, if one is set.
+    pub fn file_matcher(&self) -> Result<Option<FileMatcher>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+
+        Ok(Some(FileMatcher(Glob::new(file)?.compile_matcher())))
+    }
+}
+
+/// The points to delete, which must match every field that is set.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Any of these kinds of document.
+    pub kinds: Vec<DocumentKind>,
+    pub repo: Option<String>,
+    /// Only points indexed without a repository, as `embed` does.
+    pub without_repo: bool,
+    /// Points from any of these files, relative to the repository root. Discussions match if
+    /// they mention one of them.
+    pub files: Vec<String>,
+    /// Points from files anywhere under this directory.
+    pub dir: Option<String>,
+    pub crate_name: Option<String>,
+    /// Code with this symbol name, such as `Qdrant::search`.
+    pub symbol: Option<String>,
+    /// Any of these point IDs.
+    pub ids: Vec<String>,
+}
+
+impl Selection {
+    /// Whether nothing is selected on, which would select every point.
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+            && self.repo.is_none()
+            && !self.without_repo
+            && self.files.is_empty()
+            && self.dir.is_none()
+            && self.crate_name.is_none()
+            && self.symbol.is_none()
+            && self.ids.is_empty()
+    }
+
+    pub fn to_qdrant(&self) -> Filter {
+        let mut must = Vec::new();
+
+        if !self.kinds.is_empty() {
+            let kinds: Vec<String> = self.kinds.iter().map(ToString::to_string).collect();
+            must.push(Condition::matches("kind", kinds));
+        }
+        if let Some(repo) = &self.repo {
+            must.push(Condition::matches("repo", repo.clone()));
+        }
+        if self.without_repo {
+            must.push(Condition::is_empty("repo"));
+        }
+        if !self.files.is_empty() {
+            must.push(Condition::matches("files", self.files.clone()));
+        }
+        if let Some(dir) = self.dir() {
+            must.push(Condition::matches("dirs", dir));
+        }
+        if let Some(crate_name) = &self.crate_name {
+            must.push(Condition::matches("crate", crate_name.replace('-', "_")));
+        }
+        if let Some(symbol) = &self.symbol {
+            must.push(Condition::matches("symbol", symbol.clone()));
+        }
+        if !self.ids.is_empty() {
+            must.push(Condition::has_id(self.ids.iter().map(|id| point_id(id))));
+        }
+
+        Filter::must(must)
+    }
+
+    /// Whether a point is selected, the way Qdrant would apply [`Selection::to_qdrant`].
+    pub(crate) fn matches(&self, id: &str, payload: &HashMap<String, Value>) -> bool {
+        let kinds: Vec<String> = self.kinds.iter().map(ToString::to_string).collect();
+        let is = |key, value: Option<String>| {
+            value.is_none_or(|value| has_keyword(payload, key, &value))
+        };
+
+        (kinds.is_empty() || kinds.iter().any(|kind| has_keyword(payload, "kind", kind)))
+            && is("repo", self.repo.clone())
+            && !(self.without_repo && payload.contains_key("repo"))
+            && (self.files.is_empty()
+                || self
+                    .files
+                    .iter()
+                    .any(|file| has_keyword(payload, "files", file)))
+            && is("dirs", self.dir())
+            && is(
+                "crate",
+                self.crate_name.as_ref().map(|x| x.replace('-', "_")),
+            )
+            && is("symbol", self.symbol.clone())
+            && (self.ids.is_empty() || self.ids.iter().any(|x| x == id))
+    }
+
+    /// The directory as stored in `dirs`, without a trailing slash.
+    fn dir(&self) -> Option<String> {
+        let dir = self.dir.as_ref()?.trim_end_matches('/');
+        Some(dir.strip_prefix("./").unwrap_or(dir).to_string())
+    }
+}
+
+/// Checks documents against a file glob.
+pub struct FileMatcher(GlobMatcher);
+
+impl FileMatcher {
+    pub fn matches(&self, document: &Document) -> bool {
+        document_files(document)
+            .iter()
+            .any(|file| self.0.is_match(file))
+    }
+
+    /// Like [`FileMatcher::matches`], with the files stored in a point's payload.
+    pub(crate) fn matches_payload(&self, payload: &HashMap<String, Value>) -> bool {
+        payload
+            .get("files")
+            .and_then(Value::as_list)
+            .is_some_and(|files| {
+                files
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|file| self.0.is_match(file))
+            })
+    }
+}
+
+/// Whether a payload field is `value` or a list containing it, which is how Qdrant matches
+/// keywords.
+pub(crate) fn has_keyword(payload: &HashMap<String, Value>, key: &str, value: &str) -> bool {
+    match payload.get(key) {
+        Some(field) => match field.as_list() {
+            Some(list) => list.iter().any(|x| x.as_str().is_some_and(|x| x == value)),
+            None => field.as_str().is_some_and(|x| x == value),
+        },
+        None => false,
+    }
+}
+
+/// The filterable fields of a document, to be stored in its payload.
+pub fn payload(document: &Document) -> HashMap<String, Value> {
+    let mut payload: HashMap<String, Value> = HashMap::new();
+    payload.insert("kind".to_string(), document.kind().to_string().into());
+    if let Some(repo) = document.repo() {
+        payload.insert("repo".to_string(), repo.to_string().into());
+    }
+
+    let files = document_files(document);
+    let dirs: Vec<String> = files.iter().flat_map(|file| ancestors(file)).collect();
+    payload.insert("files".to_string(), files.into());
+    payload.insert("dirs".to_string(), dirs.into());
+    payload.insert("parent".to_string(), parent(document).into());
+
+    if let Document::Code(code) = document {
+        code_payload(code, &mut payload);
+    }
+
+    payload
+}
+
+fn code_payload(code: &TCode, payload: &mut HashMap<String, Value>) {
+    payload.insert("code_type".to_string(), code.code_type.to_string().into());
+    payload.insert("symbol".to_string(), symbol_name(code).into());
+    payload.insert("visibility".to_string(), code.visibility.to_string().into());
+    payload.insert("test".to_string(), code.test.into());
+
+    let Some(context) = &code.context else {
+        return;
+    };
+    if let Some(crate_name) = &context.crate_name {
+        payload.insert("crate".to_string(), crate_name.clone().into());
+    }
+    if let Some(module) = &context.module_path {
+        // every enclosing module, so that filtering on `parser` also finds `parser::source`
+        let segments: Vec<&str> = module.split("::").collect();
+        let modules: Vec<String> = (1..=segments.len())
+            .map(|len| segments[..len].join("::"))
+            .collect();
+        payload.insert("modules".to_string(), modules.into());
+    }
+}
+
+/// What a document is grouped under by [`GroupBy::Parent`]: the type a method is implemented on,
+/// the pull request a review comment was left on, or else the document itself.
+fn parent(document: &Document) -> String {
+    match document {
+        Document::Code(code) => code
+            .context
+            .as_ref()
+            .and_then(|context| context.struct_name.clone())
+            .unwrap_or_else(|| symbol_name(code)),
+        Document::Discussion(discussion) => format!("{}#{}", discussion.repo, discussion.number),
+    }
+}
+
+/// The group a search result is put in, if the document has anything to group by.
+pub(crate) fn group_key(document: &Document, group_by: GroupBy) -> Option<String> {
+    match group_by {
+        GroupBy::File => document_files(document).into_iter().next(),
+        GroupBy::Parent => Some(parent(document)),
+    }
+}
+
+fn document_files(document: &Document) -> Vec<String> {
+    match document {
+        Document::Code(code) => code
+            .context
+            .as_ref()
+            .and_then(|context| context.file_path.clone())
+            .into_iter()
+            .collect(),
+        Document::Discussion(TDiscussion { files, .. }) => files.clone(),
+    }
+}
+
+/// Every directory a file is in, e.g. `llms` and `llms/src` for `llms/src/lib.rs`.
+fn ancestors(file: &str) -> Vec<String> {
+    Path::new(file)
+        .ancestors()
+        .skip(1)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| dir.to_string_lossy().to_string())
+        .collect()
+}
+
+/// The directory before the first wildcard of a glob, or `None` if it has no wildcards.
+fn glob_dir(glob: &str) -> Option<String> {
+    let wildcard = glob.find(['*', '?', '[', '{'])?;
+    let dir = glob[..wildcard].rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    Some(dir.to_string())
+}
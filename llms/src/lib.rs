@@ -1,102 +1,152 @@
-use std::path::PathBuf;
-
+pub mod cache;
+pub mod config;
+pub mod embedding;
+pub mod export;
+pub mod filter;
+pub mod local;
+pub mod models;
 pub mod phi;
 pub mod qdrant;
+pub mod render;
+pub mod rerank;
+pub mod schema;
+pub mod similar;
+pub mod sparse;
+pub mod stats;
+pub mod store;
 pub mod tokenizer;
 
-use anyhow::Result;
-use fastembed::{
-    EmbeddingModel, InitOptions, InitOptionsUserDefined, TextEmbedding, TokenizerFiles,
-    UserDefinedEmbeddingModel,
-};
-use hf_hub::{
-    api::sync::{ApiBuilder, ApiRepo},
-    Repo, RepoType,
-};
-use parser::TCode;
+use anyhow::{anyhow, Result};
+use cache::{CacheStats, EmbeddingCache};
+use config::Config;
+use embedding::{InputKind, VectorModel};
+use models::ModelFiles;
+use parser::{Document, TCode};
+use qdrant_client::qdrant::Vector;
+use render::Renderer;
+use sparse::{SparseEncoder, SparseVector, TermCounts, LEXICAL_VECTOR};
 use std::collections::HashMap;
 
 pub struct Embedding {
-    code: TCode,
-    jinabert_embedding: Vec<f32>,
-    minilm_embedding: Vec<f32>,
+    document: Document,
+    vectors: HashMap<String, Vec<f32>>,
+    /// Weighed by the store, see [`sparse::DocumentLengths`].
+    sparse: Option<TermCounts>,
+    template_version: u32,
 }
 
 impl Embedding {
-    fn to_vectormap(&self) -> HashMap<String, Vec<f32>> {
-        let mut map = HashMap::new();
+    fn to_vectormap(&self, sparse: Option<&SparseVector>) -> HashMap<String, Vector> {
+        let mut map: HashMap<String, Vector> = self
+            .vectors
+            .iter()
+            .map(|(name, vector)| (name.clone(), vector.clone().into()))
+            .collect();
 
-        map.insert("code".to_string(), self.jinabert_embedding.clone());
-        map.insert("nlp".to_string(), self.minilm_embedding.clone());
+        if let Some(sparse) = sparse {
+            map.insert(LEXICAL_VECTOR.to_string(), sparse.as_slice().into());
+        }
 
         map
     }
 }
 
+/// Embeds documents with every configured model, each model filling its own named vector.
 pub struct Embedder {
-    minilm: TextEmbedding,
-    jina_bert: TextEmbedding,
+    models: Vec<(String, VectorModel)>,
+    renderer: Renderer,
+    cache: Option<EmbeddingCache>,
+    sparse: Option<SparseEncoder>,
 }
 
-pub struct CombinedEmbedding(Vec<f32>, Vec<f32>);
-
-impl CombinedEmbedding {
-    pub fn into_vectormap(self) -> HashMap<String, Vec<f32>> {
-        let mut map = HashMap::new();
-
-        map.insert("code".to_string(), self.0);
-        map.insert("nlp".to_string(), self.1);
-
-        map
-    }
+/// A prompt embedded with every model.
+pub struct CombinedEmbedding {
+    /// The dense vectors, by name.
+    pub dense: HashMap<String, Vec<f32>>,
+    pub sparse: Option<SparseVector>,
 }
 
 impl Embedder {
+    /// The default models: jina-embeddings-v2-base-code for `code` and all-MiniLM-L6-v2 for `nlp`.
     pub fn new() -> Result<Self> {
-        // With custom InitOptions
-        let minilm = TextEmbedding::try_new(InitOptions {
-            model_name: EmbeddingModel::AllMiniLML6V2,
-            show_download_progress: true,
-            ..Default::default()
-        })
-        .unwrap();
-
-        let token = std::env::var("HF_TOKEN").unwrap();
-        let api = ApiBuilder::new().with_token(Some(token)).build()?;
-        let model_id = "jinaai/jina-embeddings-v2-base-code".to_string();
+        Self::from_config(&Config::default())
+    }
 
-        let repo = api.repo(Repo::with_revision(
-            model_id,
-            RepoType::Model,
-            "main".to_string(),
-        ));
+    pub fn from_config(config: &Config) -> Result<Self> {
+        if config.embeddings.is_empty() {
+            return Err(anyhow!("no embedding models are configured"));
+        }
 
-        let jina_bert_code = TokenizerFilePaths::new(repo)?.into_model()?;
+        let files = ModelFiles::new(&config.models)?;
+        let models = config
+            .embeddings
+            .iter()
+            .map(|(name, model)| Ok((name.clone(), VectorModel::from_config(model, &files)?)))
+            .collect::<Result<_>>()?;
 
-        let jina_bert = TextEmbedding::try_new_from_user_defined(
-            jina_bert_code,
-            InitOptionsUserDefined::default(),
-        )?;
+        Ok(Self {
+            models,
+            renderer: Renderer::new(&config.templates),
+            cache: EmbeddingCache::from_config(&config.embedding_cache),
+            sparse: config
+                .sparse
+                .enabled
+                .then(|| SparseEncoder::new(&config.sparse)),
+        })
+    }
 
-        Ok(Self { minilm, jina_bert })
+    /// Whether documents also get a lexical sparse vector.
+    pub fn has_sparse(&self) -> bool {
+        self.sparse.is_some()
     }
 
-    pub fn embed_code(&self, documents: Vec<TCode>) -> Result<Vec<Embedding>> {
-        let embeddings_as_text: Vec<String> = documents
+    /// The model behind each dense vector.
+    pub fn models(&self) -> impl Iterator<Item = (&str, &VectorModel)> {
+        self.models
             .iter()
-            .map(|text| serde_json::to_string_pretty(text).unwrap())
-            .collect();
+            .map(|(name, model)| (name.as_str(), model))
+    }
 
-        let jinabert_embeddings = self.jina_bert.embed(embeddings_as_text.clone(), None)?;
-        let minilm_embeddings = self.minilm.embed(embeddings_as_text, None)?;
+    /// The version of the templates documents are rendered with.
+    pub fn template_version(&self) -> u32 {
+        self.renderer.version()
+    }
+
+    /// The name and dimension of each dense vector.
+    pub fn vectors(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.models
+            .iter()
+            .map(|(name, model)| (name.as_str(), model.dimension()))
+    }
 
-        let embeddings = itertools::izip!(documents, jinabert_embeddings, minilm_embeddings);
+    pub fn embed_code(&self, documents: Vec<TCode>) -> Result<Vec<Embedding>> {
+        self.embed_documents(documents.into_iter().map(Document::Code).collect())
+    }
 
-        let embeddings = embeddings
-            .map(|(code, jinabert_embedding, minilm_embedding)| Embedding {
-                jinabert_embedding,
-                minilm_embedding,
-                code,
+    pub fn embed_documents(&self, documents: Vec<Document>) -> Result<Vec<Embedding>> {
+        let mut vectors = vec![HashMap::new(); documents.len()];
+        for (name, model) in &self.models {
+            let texts = documents
+                .iter()
+                .map(|document| self.renderer.render(name, document))
+                .collect();
+
+            let embeddings = self.embed_cached(model, InputKind::Passage, texts)?;
+            for (vectors, embedding) in vectors.iter_mut().zip(embeddings) {
+                vectors.insert(name.clone(), embedding);
+            }
+        }
+
+        let embeddings = documents
+            .into_iter()
+            .zip(vectors)
+            .map(|(document, vectors)| Embedding {
+                sparse: self.sparse.as_ref().map(|sparse| {
+                    sparse.encode_passage(&self.renderer.render(LEXICAL_VECTOR, &document))
+                }),
+                document,
+                vectors,
+                template_version: self.renderer.version(),
             })
             .collect();
 
@@ -104,68 +154,80 @@ impl Embedder {
     }
 
     pub fn embed_prompt(&self, prompt: String) -> Result<CombinedEmbedding> {
-        let prompt = vec![prompt];
+        let mut vectors = HashMap::new();
+
+        for (name, model) in &self.models {
+            let embedding = self
+                .embed_cached(model, InputKind::Query, vec![prompt.clone()])?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("no embedding was returned for {name}"))?;
+
+            vectors.insert(name.clone(), embedding);
+        }
+
+        Ok(CombinedEmbedding {
+            sparse: self
+                .sparse
+                .as_ref()
+                .map(|sparse| sparse.encode_query(&prompt)),
+            dense: vectors,
+        })
+    }
 
-        let minilm_embedding = self
-            .minilm
-            .embed(prompt.clone(), None)?
-            .into_iter()
-            .next()
-            .unwrap();
+    /// Embeds `text` as a document with the model behind `vector`, without rendering it first.
+    pub fn embed_passage(&self, vector: &str, text: String) -> Result<Vec<f32>> {
+        let (_, model) = self
+            .models()
+            .find(|(name, _)| *name == vector)
+            .ok_or_else(|| anyhow!("there is no `{vector}` vector"))?;
 
-        let jinabert_embedding = self
-            .jina_bert
-            .embed(prompt, None)?
+        self.embed_cached(model, InputKind::Passage, vec![text])?
             .into_iter()
             .next()
-            .unwrap();
-
-        Ok(CombinedEmbedding(jinabert_embedding, minilm_embedding))
+            .ok_or_else(|| anyhow!("no embedding was returned for {vector}"))
     }
-}
 
-struct TokenizerFilePaths {
-    onnx_file: PathBuf,
-    tokenizer_file: PathBuf,
-    tokenizer_config_file: PathBuf,
-    config_file: PathBuf,
-    special_tokens_map_file: PathBuf,
-}
-
-impl TokenizerFilePaths {
-    fn new(repo: ApiRepo) -> Result<Self> {
-        let onnx_file = repo.get("onnx/model_quantized.onnx")?;
-        let tokenizer_file = repo.get("tokenizer.json")?;
-        let tokenizer_config_file = repo.get("tokenizer_config.json")?;
-        let config_file = repo.get("config.json")?;
-        let special_tokens_map_file = repo.get("special_tokens_map.json")?;
-
-        Ok(Self {
-            onnx_file,
-            tokenizer_file,
-            tokenizer_config_file,
-            config_file,
-            special_tokens_map_file,
-        })
+    /// Hits and misses of the embedding cache, if it is enabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(EmbeddingCache::stats)
     }
 
-    fn into_model(self) -> Result<UserDefinedEmbeddingModel> {
-        let onnx_file = std::fs::read(self.onnx_file)?;
-        let tokenizer_file = std::fs::read(self.tokenizer_file)?;
-        let tokenizer_config_file = std::fs::read(self.tokenizer_config_file)?;
-        let config_file = std::fs::read(self.config_file)?;
-        let special_tokens_map_file = std::fs::read(self.special_tokens_map_file)?;
-
-        let tokenizer_files = TokenizerFiles {
-            tokenizer_file,
-            tokenizer_config_file,
-            config_file,
-            special_tokens_map_file,
+    /// Formats the texts as queries or passages and embeds them with the matching encoder, only
+    /// computing the texts that aren't in the cache yet.
+    fn embed_cached(
+        &self,
+        model: &VectorModel,
+        kind: InputKind,
+        texts: Vec<String>,
+    ) -> Result<Vec<Vec<f32>>> {
+        let texts: Vec<String> = texts.iter().map(|text| model.format(kind, text)).collect();
+        let provider = model.encoder(kind);
+        let Some(cache) = &self.cache else {
+            return provider.embed(texts);
         };
+        let model = provider.model_name();
 
-        Ok(UserDefinedEmbeddingModel {
-            onnx_file,
-            tokenizer_files,
-        })
+        let mut embeddings: Vec<Option<Vec<f32>>> =
+            texts.iter().map(|text| cache.get(model, text)).collect();
+        let missing: Vec<usize> = (0..texts.len())
+            .filter(|&i| embeddings[i].is_none())
+            .collect();
+
+        if !missing.is_empty() {
+            let computed = provider.embed(missing.iter().map(|&i| texts[i].clone()).collect())?;
+            for (i, embedding) in missing.into_iter().zip(computed) {
+                // a cache that can't be written to only costs time, so it shouldn't fail embedding
+                let _ = cache.put(model, &texts[i], &embedding);
+                embeddings[i] = Some(embedding);
+            }
+        }
+
+        embeddings
+            .into_iter()
+            .map(|embedding| {
+                embedding.ok_or_else(|| anyhow!("{model} returned too few embeddings"))
+            })
+            .collect()
     }
 }
@@ -0,0 +1,644 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use parser::DocumentKind;
+use qdrant_client::qdrant::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{NamespaceMode, QdrantConfig};
+use crate::export::ExportedPoint;
+use crate::filter::{has_keyword, Selection};
+use crate::schema::Schema;
+use crate::similar::Seed;
+use crate::sparse::{self, DocumentLengths, SparseVector, LEXICAL_VECTOR};
+use crate::store::{
+    check_namespace, check_selection, commit_key, into_document, point_payload, CollectionStatus,
+    Hit, InsertProgress, InsertReport, Migration, SearchMode, SearchOptions, SearchResult,
+    SeedPoint, VectorStore, MIGRATION_PAGE, NAMESPACE_KEY, PRESERVED_KEYS, SEED_LIMIT,
+};
+use crate::{CombinedEmbedding, Embedder, Embedding};
+
+/// A store kept in memory and saved to `<dir>/<collection>.json` after every change, so that
+/// indexing and searching a few repositories needs nothing but the models.
+///
+/// Searches compare the prompt with every point instead of using an index.
+#[derive(Clone)]
+pub struct LocalStore {
+    dir: PathBuf,
+    /// The collection of the configured namespace.
+    collection: String,
+    /// The collection before any namespace is applied.
+    base_collection: String,
+    namespace_mode: NamespaceMode,
+    /// The namespace points are tagged and filtered with, when namespaces share a collection.
+    tenant: Option<String>,
+    index: Arc<RwLock<Index>>,
+}
+
+/// The contents of a collection's file.
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    schema: Option<Schema>,
+    points: Vec<LocalPoint>,
+    /// The commits repositories were last synced at, by [`commit_key`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    commits: BTreeMap<String, String>,
+    #[serde(default)]
+    lengths: DocumentLengths,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct LocalPoint {
+    id: String,
+    vectors: HashMap<String, Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sparse: Option<SparseVector>,
+    payload: HashMap<String, Value>,
+}
+
+impl LocalStore {
+    /// Opens the collection named in `config` under `dir`, which is created on the first write.
+    pub fn open(dir: PathBuf, config: &QdrantConfig) -> Result<Self> {
+        let mut store = Self {
+            dir,
+            collection: config.collection.clone(),
+            base_collection: config.collection.clone(),
+            namespace_mode: config.namespace_mode,
+            tenant: None,
+            index: Arc::default(),
+        };
+        if let Some(namespace) = &config.namespace {
+            store.scope_to(namespace)?;
+        }
+        store.index = Arc::new(RwLock::new(Index::read(&store.path())?));
+
+        Ok(store)
+    }
+
+    /// The file the collection is saved to.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.collection))
+    }
+
+    fn scope_to(&mut self, namespace: &str) -> Result<()> {
+        check_namespace(namespace)?;
+        match self.namespace_mode {
+            NamespaceMode::Collection => {
+                self.collection = format!("{}_{namespace}", self.base_collection);
+            }
+            NamespaceMode::Tenant => self.tenant = Some(namespace.to_string()),
+        }
+
+        Ok(())
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Index>> {
+        self.index
+            .read()
+            .map_err(|_| anyhow!("a write to {} panicked", self.path().display()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Index>> {
+        self.index
+            .write()
+            .map_err(|_| anyhow!("a write to {} panicked", self.path().display()))
+    }
+
+    /// Writes the collection to a temporary file first, so an interrupted save leaves the
+    /// previous contents intact.
+    fn save(&self, index: &Index, path: &Path) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+
+        let tmp = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, index)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Whether a point belongs to the namespace, if namespaces share the collection.
+    fn in_scope(&self, point: &LocalPoint) -> bool {
+        self.tenant
+            .as_ref()
+            .is_none_or(|tenant| has_keyword(&point.payload, NAMESPACE_KEY, tenant))
+    }
+
+    /// Weighs the terms of a lexical query by how many of the namespace's points contain them.
+    fn with_idf(&self, index: &Index, query: &SparseVector) -> SparseVector {
+        let mut frequencies: HashMap<u32, u64> = query.iter().map(|&(term, _)| (term, 0)).collect();
+        let mut documents = 0;
+
+        for point in index.points.iter().filter(|point| self.in_scope(point)) {
+            let Some(sparse) = &point.sparse else {
+                continue;
+            };
+            documents += 1;
+            for (term, _) in sparse {
+                if let Some(frequency) = frequencies.get_mut(term) {
+                    *frequency += 1;
+                }
+            }
+        }
+
+        let frequencies: Vec<u64> = query.iter().map(|(term, _)| frequencies[term]).collect();
+        sparse::with_idf(query, documents, &frequencies)
+    }
+
+    fn is_repo_code(&self, point: &LocalPoint, repo: &str) -> bool {
+        self.in_scope(point)
+            && has_keyword(&point.payload, "repo", repo)
+            && has_keyword(&point.payload, "kind", &DocumentKind::Code.to_string())
+    }
+
+    /// The point a document is stored as, under a new ID, given its weighed lexical vector.
+    fn point(&self, embedding: &Embedding, sparse: Option<SparseVector>) -> Result<LocalPoint> {
+        Ok(LocalPoint {
+            id: uuid::Uuid::new_v4().to_string(),
+            vectors: embedding.vectors.clone(),
+            sparse,
+            payload: point_payload(embedding, self.tenant.as_deref())?,
+        })
+    }
+}
+
+impl VectorStore for LocalStore {
+    fn with_namespace(&self, namespace: &str) -> Result<Self> {
+        let mut store = self.clone();
+        store.scope_to(namespace)?;
+        if store.collection != self.collection {
+            store.index = Arc::new(RwLock::new(Index::read(&store.path())?));
+        }
+
+        Ok(store)
+    }
+
+    fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    async fn ensure_collection(&self, embedder: &Embedder) -> Result<CollectionStatus> {
+        let current = Schema::new(embedder);
+        let mut index = self.write()?;
+
+        let Some(indexed) = &index.schema else {
+            index.schema = Some(current);
+            self.save(&index, &self.path())?;
+            return Ok(CollectionStatus::Created);
+        };
+
+        let conflicts = indexed.conflicts(&current);
+        if !conflicts.is_empty() {
+            bail!(
+                "collection {} doesn't match the configured models: {}; run `migrate` to re-embed it",
+                self.collection,
+                conflicts.join(", ")
+            );
+        }
+
+        let outdated = indexed.outdated(&current);
+        Ok(match outdated.is_empty() {
+            true => CollectionStatus::Ready,
+            false => CollectionStatus::Outdated(outdated),
+        })
+    }
+
+    async fn indexed_schema(&self) -> Result<Option<Schema>> {
+        Ok(self.read()?.schema.clone())
+    }
+
+    async fn for_each_point(
+        &self,
+        mut visit: impl FnMut(String, HashMap<String, Value>),
+    ) -> Result<()> {
+        let index = self.read()?;
+        for point in index.points.iter().filter(|point| self.in_scope(point)) {
+            visit(point.id.clone(), point.payload.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn export_points(
+        &self,
+        mut visit: impl FnMut(ExportedPoint) -> Result<()>,
+    ) -> Result<()> {
+        let index = self.read()?;
+        for point in index.points.iter().filter(|point| self.in_scope(point)) {
+            let mut payload = point.payload.clone();
+            payload.remove(NAMESPACE_KEY);
+
+            visit(ExportedPoint {
+                id: point.id.clone(),
+                vectors: point.vectors.clone(),
+                sparse: point.sparse.clone(),
+                payload,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    async fn import_points(&self, points: Vec<ExportedPoint>) -> Result<()> {
+        let mut index = self.write()?;
+        let mut positions: HashMap<String, usize> = index
+            .points
+            .iter()
+            .enumerate()
+            .map(|(position, point)| (point.id.clone(), position))
+            .collect();
+
+        for point in points {
+            let mut payload = point.payload;
+            payload.remove(NAMESPACE_KEY);
+            if let Some(tenant) = &self.tenant {
+                payload.insert(NAMESPACE_KEY.to_string(), tenant.clone().into());
+            }
+            let point = LocalPoint {
+                id: point.id,
+                vectors: point.vectors,
+                sparse: point.sparse,
+                payload,
+            };
+
+            match positions.get(&point.id) {
+                Some(&position) => index.points[position] = point,
+                None => {
+                    positions.insert(point.id.clone(), index.points.len());
+                    index.points.push(point);
+                }
+            }
+        }
+
+        self.save(&index, &self.path())
+    }
+
+    /// Re-embeds every document in place. With `keep_old`, the previous contents are first
+    /// copied to `<collection>_<unix millis>.json`.
+    async fn migrate(
+        &self,
+        embedder: &Embedder,
+        keep_old: bool,
+        progress: impl Fn(usize),
+    ) -> Result<Migration> {
+        let mut index = self.write()?;
+
+        let mut from = self.collection.clone();
+        if keep_old {
+            let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+            from = format!("{}_{millis}", self.collection);
+            self.save(&index, &self.dir.join(format!("{from}.json")))?;
+        }
+
+        let mut points = Vec::with_capacity(index.points.len());
+        let mut lengths = DocumentLengths::default();
+        for page in index.points.chunks(MIGRATION_PAGE as usize) {
+            let docs = page
+                .iter()
+                .map(|point| into_document(point.payload.clone()))
+                .collect::<Result<Vec<_>>>()?;
+
+            let embeddings = embedder.embed_documents(docs)?;
+            let sparse =
+                lengths.weigh(embeddings.iter().map(|embedding| embedding.sparse.as_ref()));
+            for ((old, embedding), sparse) in page.iter().zip(&embeddings).zip(sparse) {
+                let mut point = self.point(embedding, sparse)?;
+                point.id = old.id.clone();
+                for key in PRESERVED_KEYS {
+                    match old.payload.get(*key) {
+                        Some(value) => point.payload.insert(key.to_string(), value.clone()),
+                        None => point.payload.remove(*key),
+                    };
+                }
+                points.push(point);
+            }
+            progress(points.len());
+        }
+
+        let documents = points.len();
+        index.points = points;
+        index.schema = Some(Schema::new(embedder));
+        index.lengths = lengths;
+        self.save(&index, &self.path())?;
+
+        Ok(Migration {
+            from,
+            to: self.collection.clone(),
+            documents,
+        })
+    }
+
+    /// Adds the documents and saves the collection once, so they are stored all or nothing.
+    async fn insert_docs(
+        &self,
+        docs: Vec<Embedding>,
+        progress: impl Fn(InsertProgress),
+    ) -> Result<InsertReport> {
+        let mut index = self.write()?;
+        let (sparse, lengths) = index.weigh(&docs);
+        let points = docs
+            .iter()
+            .zip(sparse)
+            .map(|(embedding, sparse)| self.point(embedding, sparse))
+            .collect::<Result<Vec<_>>>()?;
+        let total = points.len();
+
+        let previous = std::mem::replace(&mut index.lengths, lengths);
+        index.points.extend(points);
+        if let Err(err) = self.save(&index, &self.path()) {
+            let len = index.points.len() - total;
+            index.points.truncate(len);
+            index.lengths = previous;
+            return Err(err);
+        }
+
+        progress(InsertProgress {
+            inserted: total,
+            failed: 0,
+            total,
+        });
+
+        Ok(InsertReport {
+            inserted: total,
+            failures: Vec::new(),
+        })
+    }
+
+    async fn indexed_commit(&self, repo: &str) -> Result<Option<String>> {
+        let key = commit_key(self.tenant.as_deref(), repo);
+        Ok(self.read()?.commits.get(&key).cloned())
+    }
+
+    async fn set_commit(&self, repo: &str, commit: &str) -> Result<()> {
+        let mut index = self.write()?;
+        let key = commit_key(self.tenant.as_deref(), repo);
+        index.commits.insert(key, commit.to_string());
+
+        self.save(&index, &self.path())
+    }
+
+    async fn delete_code(&self, repo: &str, files: Option<Vec<String>>) -> Result<()> {
+        let mut index = self.write()?;
+        index.points.retain(|point| {
+            let in_files = files.as_ref().is_none_or(|files| {
+                files
+                    .iter()
+                    .any(|file| has_keyword(&point.payload, "files", file))
+            });
+
+            !(self.is_repo_code(point, repo) && in_files)
+        });
+        if files.is_none() {
+            let key = commit_key(self.tenant.as_deref(), repo);
+            index.commits.remove(&key);
+        }
+
+        self.save(&index, &self.path())
+    }
+
+    async fn delete(&self, selection: &Selection) -> Result<u64> {
+        check_selection(selection)?;
+
+        let mut index = self.write()?;
+        let before = index.points.len();
+        index.points.retain(|point| {
+            !(self.in_scope(point) && selection.matches(&point.id, &point.payload))
+        });
+        let deleted = before - index.points.len();
+
+        if deleted > 0 {
+            self.save(&index, &self.path())?;
+        }
+
+        Ok(deleted as u64)
+    }
+
+    async fn indexed_files(&self, repo: Option<&str>) -> Result<BTreeSet<String>> {
+        let selection = Selection {
+            kinds: vec![DocumentKind::Code],
+            repo: repo.map(ToString::to_string),
+            without_repo: repo.is_none(),
+            ..Default::default()
+        };
+
+        let index = self.read()?;
+        let files = index
+            .points
+            .iter()
+            .filter(|point| self.in_scope(point) && selection.matches(&point.id, &point.payload))
+            .filter_map(|point| point.payload.get("files")?.as_list())
+            .flat_map(|files| files.iter().filter_map(Value::as_str).cloned())
+            .collect();
+
+        Ok(files)
+    }
+
+    async fn search(
+        &self,
+        embedding: CombinedEmbedding,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let mode = options.mode;
+        let limit = options.candidates() as usize;
+        let file_matcher = options.filter.file_matcher()?;
+
+        let index = self.read()?;
+        let points: Vec<&LocalPoint> = index
+            .points
+            .iter()
+            .filter(|point| self.in_scope(point) && options.filter.matches(&point.payload))
+            .filter(|point| {
+                file_matcher
+                    .as_ref()
+                    .is_none_or(|matcher| matcher.matches_payload(&point.payload))
+            })
+            .collect();
+
+        let mut lists = Vec::new();
+        if mode != SearchMode::Sparse {
+            for (name, query) in &embedding.dense {
+                let hits = nearest(&points, limit, |point| {
+                    Some(cosine(query, point.vectors.get(name)?))
+                });
+                lists.push((name.clone(), hits));
+            }
+        }
+
+        // collections indexed without lexical vectors are searched with the dense ones only
+        let sparse = embedding.sparse.as_ref().filter(|_| index.has_lexical());
+        if mode != SearchMode::Dense {
+            if let Some(sparse) = sparse {
+                let sparse = self.with_idf(&index, sparse);
+                let query: HashMap<u32, f32> = sparse.iter().copied().collect();
+                let hits = nearest(&points, limit, |point| {
+                    let score = point
+                        .sparse
+                        .as_ref()?
+                        .iter()
+                        .filter_map(|(index, value)| Some(query.get(index)? * value))
+                        .sum::<f32>();
+                    (score > 0.0).then_some(score)
+                });
+                lists.push((LEXICAL_VECTOR.to_string(), hits));
+            }
+        }
+
+        if lists.is_empty() {
+            return Err(anyhow!(
+                "there are no vectors to search with in {mode:?} mode"
+            ));
+        }
+
+        let results = options.fuse(lists)?;
+
+        Ok(options.page(results))
+    }
+
+    async fn lookup(
+        &self,
+        seed: &Seed,
+        vector: &str,
+        repo: Option<&str>,
+    ) -> Result<Vec<SeedPoint>> {
+        let index = self.read()?;
+        let matches = index
+            .points
+            .iter()
+            .filter(|point| self.in_scope(point) && seed.matches(&point.id, &point.payload))
+            .filter(|point| repo.is_none_or(|repo| has_keyword(&point.payload, "repo", repo)))
+            .take(SEED_LIMIT as usize);
+
+        let mut points = Vec::new();
+        for point in matches {
+            let embedding = point
+                .vectors
+                .get(vector)
+                .ok_or_else(|| anyhow!("point has no `{vector}` vector"))?;
+
+            points.push(SeedPoint {
+                id: point.id.clone(),
+                vector: embedding.clone(),
+                document: into_document(point.payload.clone())?,
+            });
+        }
+
+        points.sort_by_key(|point| std::cmp::Reverse(seed.overlap(&point.document)));
+
+        Ok(points)
+    }
+
+    async fn similar(
+        &self,
+        vector: &str,
+        embedding: Vec<f32>,
+        exclude: &[String],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let file_matcher = options.filter.file_matcher()?;
+
+        let index = self.read()?;
+        let points: Vec<&LocalPoint> = index
+            .points
+            .iter()
+            .filter(|point| self.in_scope(point) && !exclude.contains(&point.id))
+            .filter(|point| options.filter.matches(&point.payload))
+            .filter(|point| {
+                file_matcher
+                    .as_ref()
+                    .is_none_or(|matcher| matcher.matches_payload(&point.payload))
+            })
+            .collect();
+
+        let results = nearest(&points, options.candidates() as usize, |point| {
+            Some(cosine(&embedding, point.vectors.get(vector)?))
+        })
+        .into_iter()
+        .map(|hit| {
+            Ok(SearchResult {
+                id: hit.id,
+                scores: HashMap::from([(vector.to_string(), hit.score)]),
+                score: hit.score,
+                rerank_score: None,
+                group: None,
+                document: into_document(hit.payload)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+        Ok(options.page(results))
+    }
+}
+
+impl Index {
+    /// Whether the points were indexed with lexical vectors.
+    fn has_lexical(&self) -> bool {
+        self.schema.as_ref().is_some_and(|schema| schema.sparse)
+    }
+
+    /// The lexical vectors of documents about to be added, weighed with the lengths of those in
+    /// the collection and theirs, and the lengths once they're added. Collections indexed without
+    /// lexical vectors get none.
+    fn weigh(&self, docs: &[Embedding]) -> (Vec<Option<SparseVector>>, DocumentLengths) {
+        let mut lengths = self.lengths;
+        let sparse = match self.has_lexical() {
+            true => lengths.weigh(docs.iter().map(|embedding| embedding.sparse.as_ref())),
+            false => vec![None; docs.len()],
+        };
+
+        (sparse, lengths)
+    }
+
+    /// The saved collection, or an empty one if it hasn't been saved yet.
+    fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("failed to read {}", path.display()))
+    }
+}
+
+/// The `limit` best scoring points, leaving out those `score` gives no score.
+fn nearest(
+    points: &[&LocalPoint],
+    limit: usize,
+    score: impl Fn(&LocalPoint) -> Option<f32>,
+) -> Vec<Hit> {
+    let mut scored: Vec<(f32, &LocalPoint)> = points
+        .iter()
+        .filter_map(|point| Some((score(point)?, *point)))
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(score, point)| Hit {
+            id: point.id.clone(),
+            score,
+            payload: point.payload.clone(),
+        })
+        .collect()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        return 0.0;
+    }
+
+    dot / norms
+}
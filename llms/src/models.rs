@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use hf_hub::api::sync::{Api, ApiBuilder};
+use hf_hub::{Cache, Repo, RepoType};
+
+use crate::config::ModelsConfig;
+
+/// Finds model files, looking in the configured model directory before the Hugging Face hub.
+///
+/// The directory may be laid out as a hub cache (`models--<org>--<name>/snapshots/...`, e.g. a
+/// copied `~/.cache/huggingface/hub`) or hold plain files under the repository id
+/// (`<org>/<name>/tokenizer.json`).
+pub struct ModelFiles {
+    dir: Option<PathBuf>,
+    /// `None` when downloading is disabled.
+    api: Option<Api>,
+}
+
+/// The files of one model repository.
+pub struct ModelRepo<'a> {
+    files: &'a ModelFiles,
+    id: String,
+    revision: String,
+    repo: Repo,
+}
+
+impl ModelFiles {
+    /// Downloads are disabled with `offline` or by setting `HF_HUB_OFFLINE=1`. `HF_TOKEN` is only
+    /// needed for gated models.
+    pub fn new(config: &ModelsConfig) -> Result<Self> {
+        let offline = config.offline
+            || std::env::var("HF_HUB_OFFLINE").is_ok_and(|var| var == "1" || var == "true");
+
+        let api = if offline {
+            None
+        } else {
+            let mut builder = ApiBuilder::new();
+            if let Ok(token) = std::env::var("HF_TOKEN") {
+                builder = builder.with_token(Some(token));
+            }
+            Some(builder.build()?)
+        };
+
+        Ok(Self {
+            dir: config.dir.clone(),
+            api,
+        })
+    }
+
+    pub fn repo(&self, id: &str, revision: &str) -> ModelRepo<'_> {
+        ModelRepo {
+            files: self,
+            id: id.to_string(),
+            revision: revision.to_string(),
+            repo: Repo::with_revision(id.to_string(), RepoType::Model, revision.to_string()),
+        }
+    }
+}
+
+impl ModelRepo<'_> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    pub fn get(&self, file: &str) -> Result<PathBuf> {
+        let mut searched = Vec::new();
+
+        if let Some(dir) = &self.files.dir {
+            let cache = Cache::new(dir.clone()).repo(self.repo.clone());
+            if let Some(path) = cache.get(file) {
+                return Ok(path);
+            }
+
+            let plain = dir.join(&self.id).join(file);
+            if plain.is_file() {
+                return Ok(plain);
+            }
+
+            searched.push(format!(
+                "{} and {}",
+                dir.join(self.repo.folder_name()).display(),
+                plain.display()
+            ));
+        }
+
+        match &self.files.api {
+            Some(api) => api
+                .repo(self.repo.clone())
+                .get(file)
+                .with_context(|| format!("downloading {file} of {}", self.id)),
+            None if searched.is_empty() => bail!(
+                "{file} of {} is needed, but downloads are disabled and no model directory is \
+                 configured",
+                self.id
+            ),
+            None => bail!(
+                "{file} of {} was not found in {} (downloads are disabled)",
+                self.id,
+                searched.join(", ")
+            ),
+        }
+    }
+}
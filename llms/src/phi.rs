@@ -14,7 +14,8 @@ use candle_nn::VarBuilder;
 use candle_transformers::generation::LogitsProcessor;
 use tokenizers::Tokenizer;
 
-use hf_hub::{api::sync::ApiBuilder, api::sync::ApiRepo, Repo, RepoType};
+use crate::config::ModelsConfig;
+use crate::models::{ModelFiles, ModelRepo};
 
 pub use ort::Result as OrtResult;
 
@@ -50,9 +51,12 @@ pub struct TextGeneration {
 
 impl TextGeneration {
     pub fn new() -> anyhow::Result<Self> {
-        let token = std::env::var("HF_TOKEN").unwrap();
+        Self::from_files(&ModelFiles::new(&ModelsConfig::default())?)
+    }
 
-        let repo = get_repo(token)?;
+    /// Loads Mistral-7B-v0.1 through `files`, so it can come from a local model directory.
+    pub fn from_files(files: &ModelFiles) -> anyhow::Result<Self> {
+        let repo = files.repo("mistralai/Mistral-7B-v0.1", "main");
         let tokenizer = get_tokenizer(&repo)?;
         let device = Device::Cpu;
         let filenames = hub_load_safetensors(&repo, "model.safetensors.index.json")?;
@@ -144,22 +148,10 @@ impl TextGeneration {
     }
 }
 
-fn get_repo(token: String) -> anyhow::Result<ApiRepo> {
-    let api = ApiBuilder::new().with_token(Some(token)).build()?;
-
-    let model_id = "mistralai/Mistral-7B-v0.1".to_string();
-
-    Ok(api.repo(Repo::with_revision(
-        model_id,
-        RepoType::Model,
-        "main".to_string(),
-    )))
-}
-
-fn get_tokenizer(repo: &ApiRepo) -> anyhow::Result<Tokenizer> {
+fn get_tokenizer(repo: &ModelRepo) -> anyhow::Result<Tokenizer> {
     let tokenizer_filename = repo.get("tokenizer.json")?;
 
-    Ok(Tokenizer::from_file(tokenizer_filename).unwrap())
+    Tokenizer::from_file(tokenizer_filename).map_err(anyhow::Error::msg)
 }
 
 #[derive(Debug, Deserialize)]
@@ -186,18 +178,12 @@ where
 }
 
 pub fn hub_load_safetensors(
-    repo: &hf_hub::api::sync::ApiRepo,
+    repo: &ModelRepo,
     json_file: &str,
 ) -> anyhow::Result<Vec<std::path::PathBuf>> {
-    let json_file = repo.get(json_file).map_err(candle_core::Error::wrap)?;
+    let json_file = repo.get(json_file)?;
     let json_file = std::fs::File::open(json_file)?;
     let json: Weightmaps = serde_json::from_reader(&json_file).map_err(candle_core::Error::wrap)?;
 
-    let pathbufs: Vec<std::path::PathBuf> = json
-        .weight_map
-        .iter()
-        .map(|f| repo.get(f).unwrap())
-        .collect();
-
-    Ok(pathbufs)
+    json.weight_map.iter().map(|f| repo.get(f)).collect()
 }
@@ -1,134 +1,1505 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use parser::TCode;
-use qdrant_client::client::{QdrantClient, QdrantClientConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::{self, StreamExt};
+use itertools::Itertools;
+use parser::DocumentKind;
+use qdrant_client::client::{Payload, QdrantClient, QdrantClientConfig};
+use qdrant_client::qdrant::alias_operations::Action;
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::vectors::VectorsOptions;
 use qdrant_client::qdrant::vectors_config::Config;
 use qdrant_client::qdrant::{
-    CreateCollection, Distance, PointStruct, SearchBatchPoints, SearchPoints, Value, VectorParams,
+    quantization_config, AliasOperations, BinaryQuantization, ChangeAliases, CompressionRatio,
+    Condition, CountPoints, CreateAlias, CreateCollection, DeleteAlias, Distance, FieldType,
+    Filter, HnswConfigDiff, PointId, PointStruct, ProductQuantization, QuantizationConfig,
+    QuantizationSearchParams, QuantizationType, ScalarQuantization, ScoredPoint, ScrollPoints,
+    SearchBatchPoints, SearchParams, SearchPointGroups, SearchPoints, SnapshotDescription,
+    SparseIndices, SparseVectorConfig, SparseVectorParams, Value, Vector, VectorParams,
     VectorParamsMap, VectorsConfig,
 };
+use tonic::{Code, Status};
 
-use crate::{CombinedEmbedding, Embedding};
+use crate::config::{
+    Compression, GroupBy, IndexConfig, NamespaceMode, QdrantConfig, Quantization, UpsertConfig,
+};
+use crate::export::ExportedPoint;
+use crate::filter::{Selection, INDEXED_FIELDS};
+use crate::schema::{Schema, VectorSchema};
+use crate::similar::{point_id, Seed};
+use crate::sparse::{self, DocumentLengths, SparseVector, LEXICAL_VECTOR};
+use crate::store::{
+    check_namespace, check_selection, commit_key, into_document, point_payload, BatchFailure,
+    CollectionStatus, Hit, InsertProgress, InsertReport, Migration, SearchMode, SearchOptions,
+    SearchResult, SeedPoint, VectorStore, MIGRATION_PAGE, NAMESPACE_KEY, PRESERVED_KEYS,
+    SEED_LIMIT, TERMS_KEY,
+};
+use crate::{CombinedEmbedding, Embedder, Embedding};
 
 pub const COLLECTION_NAME: &str = "DUCKYDUCK";
 
+/// The ID of the point holding the collection's [`Schema`]. It has no vectors, so searches never
+/// find it.
+const SCHEMA_POINT: &str = "00000000-0000-0000-0000-000000000000";
+
+/// The payload key of the schema point holding the collection's [`DocumentLengths`].
+const LENGTHS_KEY: &str = "lengths";
+
+/// How many points are read at a time when going through a whole collection.
+const SCROLL_PAGE: u32 = 256;
+
+#[derive(Clone)]
 pub struct Qdrant {
-    qdrant: QdrantClient,
+    qdrant: Arc<QdrantClient>,
+    /// The collection of the configured namespace.
+    collection: String,
+    /// The collection before any namespace is applied.
+    base_collection: String,
+    namespace_mode: NamespaceMode,
+    /// The namespace points are tagged and filtered with, when namespaces share a collection.
+    tenant: Option<String>,
+    upsert: UpsertConfig,
+    index: IndexConfig,
+    /// For the snapshot endpoints, which aren't available over gRPC.
+    rest: RestClient,
+}
+
+#[derive(Clone)]
+struct RestClient {
+    agent: ureq::Agent,
+    url: String,
+    api_key: Option<String>,
+}
+
+/// A snapshot of a collection, stored on the Qdrant instance.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    /// When it was created, in seconds since the Unix epoch.
+    pub created_at: Option<i64>,
+    pub size: u64,
+    /// The SHA-256 digest of the snapshot file.
+    pub checksum: Option<String>,
+}
+
+/// The outcome of [`Qdrant::restore_snapshot`].
+#[derive(Debug, Clone)]
+pub struct Restore {
+    /// The collection that was replaced, if there was one.
+    pub from: Option<String>,
+    pub to: String,
 }
 
 impl Qdrant {
     pub fn from_url(url: &str, api_key: Option<String>) -> Result<Self> {
-        let cfg = QdrantClientConfig::from_url(url)
-            .with_api_key(api_key)
+        Self::from_config(&QdrantConfig {
+            url: url.to_string(),
+            api_key,
+            ..Default::default()
+        })
+    }
+
+    pub fn from_config(config: &QdrantConfig) -> Result<Self> {
+        let mut url = config.url.clone();
+        if config.tls {
+            if let Some(rest) = url.strip_prefix("http://") {
+                url = format!("https://{rest}");
+            }
+        }
+        let api_key = config
+            .api_key
+            .clone()
+            .or_else(|| std::env::var("QDRANT_API_KEY").ok());
+
+        let client = QdrantClientConfig::from_url(&url)
+            .with_api_key(api_key.clone())
             .build()?;
 
-        Ok(Self { qdrant: cfg })
+        let mut rest_url = match &config.rest_url {
+            Some(rest_url) => rest_url.trim_end_matches('/').to_string(),
+            None => {
+                let url = url.trim_end_matches('/');
+                match url.strip_suffix(":6334") {
+                    Some(host) => format!("{host}:6333"),
+                    None => url.to_string(),
+                }
+            }
+        };
+        if config.tls {
+            if let Some(rest) = rest_url.strip_prefix("http://") {
+                rest_url = format!("https://{rest}");
+            }
+        }
+
+        let qdrant = Self {
+            qdrant: Arc::new(client),
+            collection: config.collection.clone(),
+            base_collection: config.collection.clone(),
+            namespace_mode: config.namespace_mode,
+            tenant: None,
+            upsert: config.upsert.clone(),
+            index: config.index.clone(),
+            rest: RestClient {
+                agent: ureq::Agent::new(),
+                url: rest_url,
+                api_key,
+            },
+        };
+
+        match &config.namespace {
+            Some(namespace) => qdrant.with_namespace(namespace),
+            None => Ok(qdrant),
+        }
     }
 
-    pub async fn create_collection(&self) -> Result<()> {
-        let map = collection_params();
+    /// Adds the namespace condition to `filter`, if namespaces share the collection.
+    fn scoped(&self, mut filter: Filter) -> Filter {
+        if let Some(tenant) = &self.tenant {
+            filter
+                .must
+                .push(Condition::matches(NAMESPACE_KEY, tenant.clone()));
+        }
+
+        filter
+    }
+
+    /// The filter of an unfiltered request, which is only scoped to the namespace.
+    fn scope(&self) -> Option<Filter> {
+        self.tenant
+            .is_some()
+            .then(|| self.scoped(Filter::default()))
+    }
+
+    fn repo_code_filter(&self, repo: &str) -> Filter {
+        self.scoped(Filter::must([
+            Condition::matches("repo", repo.to_string()),
+            Condition::matches("kind", DocumentKind::Code.to_string()),
+        ]))
+    }
+
+    /// The collection the configured name refers to: the target of the alias, or the collection
+    /// itself if it predates aliases.
+    async fn physical_collection(&self) -> Result<Option<String>> {
+        let aliases = self.qdrant.list_aliases().await?.aliases;
+        if let Some(alias) = aliases
+            .into_iter()
+            .find(|alias| alias.alias_name == self.collection)
+        {
+            return Ok(Some(alias.collection_name));
+        }
+
+        let exists = self.qdrant.collection_exists(&self.collection).await?;
+        Ok(exists.then(|| self.collection.clone()))
+    }
+
+    /// A name for a new generation of the collection.
+    fn versioned_name(&self) -> Result<String> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        Ok(format!("{}_{millis}", self.collection))
+    }
+
+    /// Creates a collection with a named vector for each of the embedder's models, the lexical
+    /// sparse vector if it is enabled, indexes on the payload fields searches filter on, and the
+    /// schema it is indexed with. The vectors are indexed as set in [`IndexConfig`].
+    async fn create_collection(&self, name: &str, embedder: &Embedder) -> Result<()> {
+        let map = collection_params(embedder, &self.index);
+        let sparse_vectors_config = embedder.has_sparse().then(|| SparseVectorConfig {
+            map: HashMap::from([(LEXICAL_VECTOR.to_string(), SparseVectorParams::default())]),
+        });
 
         self.qdrant
             .create_collection(&CreateCollection {
-                collection_name: COLLECTION_NAME.to_string(),
+                collection_name: name.to_string(),
                 vectors_config: Some(VectorsConfig {
                     config: Some(Config::ParamsMap(map)),
                 }),
+                sparse_vectors_config,
+                hnsw_config: hnsw_config(&self.index),
+                quantization_config: self.index.quantization.as_ref().map(quantization_config),
                 ..Default::default()
             })
             .await?;
 
+        let namespace = (NAMESPACE_KEY, FieldType::Keyword);
+        let terms = (TERMS_KEY, FieldType::Integer);
+        for (field, field_type) in INDEXED_FIELDS.iter().chain([&namespace, &terms]) {
+            self.qdrant
+                .create_field_index(name, *field, *field_type, None, None)
+                .await?;
+        }
+
+        let schema: Value = serde_json::to_value(Schema::new(embedder))?.into();
+        let point = PointStruct {
+            id: Some(SCHEMA_POINT.to_string().into()),
+            vectors: Some(HashMap::<String, Vector>::new().into()),
+            payload: HashMap::from([
+                ("kind".to_string(), "schema".into()),
+                ("schema".to_string(), schema),
+            ]),
+        };
+        self.qdrant
+            .upsert_points_blocking(name, None, vec![point], None)
+            .await?;
+
         Ok(())
     }
 
-    pub async fn insert_docs(&self, docs: Vec<Embedding>) -> Result<()> {
-        for embedding in docs {
-            let value = serde_json::to_string(&embedding.code)?;
-            let value: Value = serde_json::from_str(&value)?;
+    /// The schema stored in a collection, if it has one.
+    async fn schema(&self, collection: &str) -> Result<Option<Schema>> {
+        let res = self
+            .qdrant
+            .get_points(
+                collection,
+                None,
+                &[SCHEMA_POINT.to_string().into()],
+                Some(false),
+                Some(true),
+                None,
+            )
+            .await?;
 
-            let mut payload: HashMap<String, Value> = HashMap::new();
-            payload.insert("data".to_string(), value);
+        let Some(mut point) = res.result.into_iter().next() else {
+            return Ok(None);
+        };
+        let schema = point
+            .payload
+            .remove("schema")
+            .ok_or_else(|| anyhow!("the schema point of {collection} has no schema"))?;
 
-            let point = PointStruct {
-                id: Some(uuid::Uuid::new_v4().to_string().into()), // unique u64 or String
-                vectors: Some(embedding.to_vectormap().into()),
-                payload,
+        Ok(Some(serde_json::from_value(schema.into())?))
+    }
+
+    /// Copies the synced commits recorded on the schema point of one collection to another.
+    async fn copy_commits(&self, from: &str, to: &str) -> Result<()> {
+        let res = self
+            .qdrant
+            .get_points(
+                from,
+                None,
+                &[SCHEMA_POINT.to_string().into()],
+                Some(false),
+                Some(true),
+                None,
+            )
+            .await?;
+
+        let commits: HashMap<String, Value> = res
+            .result
+            .into_iter()
+            .flat_map(|point| point.payload)
+            .filter(|(key, _)| key.starts_with("commit:"))
+            .collect();
+        if commits.is_empty() {
+            return Ok(());
+        }
+
+        self.qdrant
+            .set_payload_blocking(
+                to,
+                None,
+                &vec![SCHEMA_POINT.to_string().into()].into(),
+                Payload::new_from_hashmap(commits),
+                None,
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// What can be told of the schema of a collection from before schemas were stored: the
+    /// vectors and their dimensions, assuming the models are the configured ones.
+    async fn legacy_schema(&self, collection: &str, current: &Schema) -> Result<Schema> {
+        let info = self.qdrant.collection_info(collection).await?;
+        let params = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params);
+
+        let mut schema = Schema {
+            version: 0,
+            vectors: BTreeMap::new(),
+            sparse: false,
+            template_version: 0,
+        };
+        let Some(params) = params else {
+            return Ok(schema);
+        };
+
+        if let Some(Config::ParamsMap(map)) = params.vectors_config.and_then(|x| x.config) {
+            for (name, params) in map.map {
+                let model = match current.vectors.get(&name) {
+                    Some(vector) if vector.dimension == params.size as usize => {
+                        vector.model.clone()
+                    }
+                    _ => "an unknown model".to_string(),
+                };
+                let vector = VectorSchema {
+                    model,
+                    query_model: current
+                        .vectors
+                        .get(&name)
+                        .and_then(|x| x.query_model.clone()),
+                    dimension: params.size as usize,
+                };
+                schema.vectors.insert(name, vector);
+            }
+        }
+        schema.sparse = params
+            .sparse_vectors_config
+            .is_some_and(|config| config.map.contains_key(LEXICAL_VECTOR));
+
+        Ok(schema)
+    }
+
+    /// Snapshots the collection on the Qdrant instance. With namespaces sharing the collection,
+    /// the snapshot holds every namespace.
+    pub async fn create_snapshot(&self) -> Result<Snapshot> {
+        let collection = self.existing_collection().await?;
+        let res = self.qdrant.create_snapshot(&collection).await?;
+
+        res.snapshot_description
+            .map(Snapshot::from)
+            .ok_or_else(|| anyhow!("Qdrant didn't describe the snapshot of {collection}"))
+    }
+
+    /// The snapshots of the collection, newest first.
+    pub async fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        let collection = self.existing_collection().await?;
+        let res = self.qdrant.list_snapshots(&collection).await?;
+
+        let mut snapshots: Vec<Snapshot> = res
+            .snapshot_descriptions
+            .into_iter()
+            .map(Snapshot::from)
+            .collect();
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created_at));
+
+        Ok(snapshots)
+    }
+
+    /// Downloads a snapshot of the collection to `path`, returning its size in bytes. The file
+    /// only appears once the download is complete.
+    pub async fn download_snapshot(&self, name: &str, path: &Path) -> Result<u64> {
+        let collection = self.existing_collection().await?;
+        let url = format!(
+            "{}/collections/{collection}/snapshots/{name}",
+            self.rest.url
+        );
+        let rest = self.rest.clone();
+        let path = path.to_path_buf();
+
+        // the REST client blocks, so the transfer runs off the async threads
+        tokio::task::spawn_blocking(move || {
+            let res = rest.request("GET", &url).call()?;
+
+            let tmp = path.with_extension("part");
+            let mut file = File::create(&tmp)
+                .with_context(|| format!("failed to create {}", tmp.display()))?;
+            let size = io::copy(&mut res.into_reader(), &mut file)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+
+            Ok(size)
+        })
+        .await?
+    }
+
+    /// Uploads a snapshot file into a new collection and points the alias at it, like
+    /// [`Qdrant::migrate`] does. The collection it replaces is deleted unless `keep_old` is set.
+    pub async fn restore_snapshot(&self, path: &Path, keep_old: bool) -> Result<Restore> {
+        let from = self.physical_collection().await?;
+        let to = self.versioned_name()?;
+
+        let url = format!(
+            "{}/collections/{to}/snapshots/upload?priority=snapshot&wait=true",
+            self.rest.url
+        );
+        let rest = self.rest.clone();
+        let path = path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || {
+            let file =
+                File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+            let filename = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "snapshot".to_string());
+            let boundary = format!("duckyduck-{}", uuid::Uuid::new_v4().simple());
+            let head = format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"snapshot\"; filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+            );
+            let tail = format!("\r\n--{boundary}--\r\n");
+            let body = head.as_bytes().chain(file).chain(tail.as_bytes());
+
+            rest.request("POST", &url)
+                .set(
+                    "Content-Type",
+                    &format!("multipart/form-data; boundary={boundary}"),
+                )
+                .send(body)?;
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+
+        if let Err(err) = self.switch_alias(from.as_deref(), &to, keep_old).await {
+            // once the collection it replaces is gone, the restored one is all that's left
+            let replaced = match &from {
+                Some(from) => self.qdrant.collection_exists(from).await.unwrap_or(false),
+                None => true,
             };
+            if replaced {
+                let _ = self.qdrant.delete_collection(&to).await;
+            }
+            return Err(err);
+        }
+
+        Ok(Restore { from, to })
+    }
+
+    /// Runs each search with Qdrant's grouping, so that a group with many matches can't crowd the
+    /// others out of the candidates. Returns the hits of each search, best first.
+    async fn search_groups(
+        &self,
+        search_points: Vec<SearchPoints>,
+        group_by: GroupBy,
+        options: &SearchOptions,
+    ) -> Result<Vec<Vec<Hit>>> {
+        let key = match group_by {
+            GroupBy::File => "files",
+            GroupBy::Parent => "parent",
+        };
+
+        let searches = search_points.into_iter().map(|search| async move {
+            let res = self
+                .qdrant
+                .search_groups(&SearchPointGroups {
+                    collection_name: search.collection_name,
+                    vector: search.vector,
+                    sparse_indices: search.sparse_indices,
+                    filter: search.filter,
+                    limit: (options.offset + options.limit) as u32,
+                    with_payload: search.with_payload,
+                    params: search.params,
+                    vector_name: search.vector_name,
+                    group_by: key.to_string(),
+                    group_size: options.group_size.max(1) as u32,
+                    ..Default::default()
+                })
+                .await?;
+
+            let mut points: Vec<ScoredPoint> = res
+                .result
+                .into_iter()
+                .flat_map(|result| result.groups)
+                .flat_map(|group| group.hits)
+                .collect();
+            points.sort_by(|a, b| b.score.total_cmp(&a.score));
+            points.dedup_by(|a, b| a.id == b.id);
+
+            Ok::<_, anyhow::Error>(hits(points))
+        });
+
+        futures::future::try_join_all(searches).await
+    }
+
+    /// The collection the configured name refers to, failing if there is none.
+    async fn existing_collection(&self) -> Result<String> {
+        self.physical_collection()
+            .await?
+            .ok_or_else(|| anyhow!("collection {} doesn't exist", self.collection))
+    }
+
+    /// Points the alias at `to` in a single operation, then deletes `from` unless `keep_old` is
+    /// set. A collection from before aliases were used has the alias's name, so it can only be
+    /// deleted right before the alias is created; if that then fails, `to` holds the only copy of
+    /// the points and is left in place.
+    async fn switch_alias(&self, from: Option<&str>, to: &str, keep_old: bool) -> Result<()> {
+        let create = AliasOperations {
+            action: Some(Action::CreateAlias(CreateAlias {
+                collection_name: to.to_string(),
+                alias_name: self.collection.clone(),
+            })),
+        };
+
+        match from {
+            Some(from) if from == self.collection => {
+                self.qdrant.delete_collection(from).await?;
+                self.update_aliases(vec![create]).await.with_context(|| {
+                    format!(
+                        "{from} was replaced by {to}, but the alias couldn't be created; \
+                         create the alias {} for {to} to use it",
+                        self.collection
+                    )
+                })?;
+            }
+            Some(from) => {
+                let delete = AliasOperations {
+                    action: Some(Action::DeleteAlias(DeleteAlias {
+                        alias_name: self.collection.clone(),
+                    })),
+                };
+                self.update_aliases(vec![delete, create]).await?;
+
+                if !keep_old {
+                    self.qdrant.delete_collection(from).await?;
+                }
+            }
+            None => self.update_aliases(vec![create]).await?,
+        }
+
+        Ok(())
+    }
+
+    async fn update_aliases(&self, actions: Vec<AliasOperations>) -> Result<()> {
+        self.qdrant
+            .update_aliases(ChangeAliases {
+                actions,
+                timeout: None,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Upserts one batch, backing off and retrying while Qdrant is unavailable or overloaded.
+    async fn upsert_batch(&self, points: Vec<PointStruct>) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let res = self
+                .qdrant
+                .upsert_points_blocking(&self.collection, None, points.clone(), None)
+                .await;
+
+            match res {
+                Ok(_) => return Ok(()),
+                Err(err) if attempt < self.upsert.retries && is_transient(&err) => {
+                    let backoff = self.upsert.backoff_ms << attempt.min(16);
+                    tokio::time::sleep(Duration::from_millis(backoff)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Weighs the terms of a lexical query by how many of the namespace's points contain them.
+    async fn with_idf(&self, query: SparseVector) -> Result<SparseVector> {
+        let count = |filter: Filter| async move {
+            let res = self
+                .qdrant
+                .count(&CountPoints {
+                    collection_name: self.collection.clone(),
+                    filter: Some(self.scoped(filter)),
+                    exact: Some(true),
+                    ..Default::default()
+                })
+                .await?;
+
+            Ok::<_, anyhow::Error>(res.result.map_or(0, |result| result.count))
+        };
+
+        let documents = count(Filter::must_not([Condition::is_empty(TERMS_KEY)]));
+        let frequencies =
+            futures::future::try_join_all(query.iter().map(|(index, _)| {
+                count(Filter::must([Condition::matches(TERMS_KEY, *index as i64)]))
+            }));
+        let (documents, frequencies) = futures::try_join!(documents, frequencies)?;
+
+        Ok(sparse::with_idf(&query, documents, &frequencies))
+    }
+
+    /// The lengths of the documents with lexical vectors in the collection, or `None` if its
+    /// points were indexed without them.
+    async fn lexical_lengths(&self) -> Result<Option<DocumentLengths>> {
+        let res = self
+            .qdrant
+            .get_points(
+                &self.collection,
+                None,
+                &[SCHEMA_POINT.to_string().into()],
+                Some(false),
+                Some(vec!["schema", LENGTHS_KEY]),
+                None,
+            )
+            .await?;
+
+        let Some(mut point) = res.result.into_iter().next() else {
+            return Ok(None);
+        };
+        let Some(schema) = point.payload.remove("schema") else {
+            return Ok(None);
+        };
+        if !serde_json::from_value::<Schema>(schema.into())?.sparse {
+            return Ok(None);
+        }
+
+        match point.payload.remove(LENGTHS_KEY) {
+            Some(lengths) => Ok(Some(serde_json::from_value(lengths.into())?)),
+            None => Ok(Some(DocumentLengths::default())),
+        }
+    }
+
+    /// Records the lengths of the documents with lexical vectors on the schema point.
+    async fn set_lengths(&self, collection: &str, lengths: DocumentLengths) -> Result<()> {
+        let mut payload = Payload::new();
+        payload.insert(LENGTHS_KEY, Value::from(serde_json::to_value(lengths)?));
+
+        self.qdrant
+            .set_payload_blocking(
+                collection,
+                None,
+                &vec![SCHEMA_POINT.to_string().into()].into(),
+                payload,
+                None,
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// The point a document is stored as, under a new ID, given its weighed lexical vector.
+    fn point(&self, embedding: &Embedding, sparse: Option<SparseVector>) -> Result<PointStruct> {
+        let mut payload = point_payload(embedding, self.tenant.as_deref())?;
+        if let Some(sparse) = &sparse {
+            payload.insert(TERMS_KEY.to_string(), terms(sparse));
+        }
+
+        Ok(PointStruct {
+            id: Some(uuid::Uuid::new_v4().to_string().into()), // unique u64 or String
+            vectors: Some(embedding.to_vectormap(sparse.as_ref()).into()),
+            payload,
+        })
+    }
+}
+
+impl VectorStore for Qdrant {
+    /// The same connection, scoped to `namespace` instead.
+    fn with_namespace(&self, namespace: &str) -> Result<Self> {
+        check_namespace(namespace)?;
+
+        let mut qdrant = self.clone();
+        match self.namespace_mode {
+            NamespaceMode::Collection => {
+                qdrant.collection = format!("{}_{namespace}", self.base_collection);
+            }
+            NamespaceMode::Tenant => qdrant.tenant = Some(namespace.to_string()),
+        }
+
+        Ok(qdrant)
+    }
+
+    /// The name of the collection documents are stored in.
+    fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Creates the collection if it doesn't exist yet, otherwise checks that it was indexed with
+    /// the embedder's models, so it is safe to call on every start.
+    ///
+    /// Collections are created under a versioned name, with the configured name as an alias, so
+    /// that [`Qdrant::migrate`] can swap in a re-embedded collection.
+    async fn ensure_collection(&self, embedder: &Embedder) -> Result<CollectionStatus> {
+        let current = Schema::new(embedder);
+
+        let Some(physical) = self.physical_collection().await? else {
+            let physical = self.versioned_name()?;
+            self.create_collection(&physical, embedder).await?;
+            self.qdrant
+                .create_alias(&physical, &self.collection)
+                .await?;
+            return Ok(CollectionStatus::Created);
+        };
+
+        let (indexed, mut outdated) = match self.schema(&physical).await? {
+            Some(schema) => (schema, Vec::new()),
+            None => (
+                self.legacy_schema(&physical, &current).await?,
+                vec!["the collection predates schema versioning".to_string()],
+            ),
+        };
+
+        let conflicts = indexed.conflicts(&current);
+        if !conflicts.is_empty() {
+            bail!(
+                "collection {} doesn't match the configured models: {}; run `migrate` to re-embed it",
+                self.collection,
+                conflicts.join(", ")
+            );
+        }
+
+        outdated.extend(indexed.outdated(&current));
+        Ok(match outdated.is_empty() {
+            true => CollectionStatus::Ready,
+            false => CollectionStatus::Outdated(outdated),
+        })
+    }
+
+    async fn indexed_schema(&self) -> Result<Option<Schema>> {
+        match self.physical_collection().await? {
+            Some(physical) => self.schema(&physical).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn for_each_point(
+        &self,
+        mut visit: impl FnMut(String, HashMap<String, Value>),
+    ) -> Result<()> {
+        let mut filter = self.scope().unwrap_or_default();
+        filter
+            .must_not
+            .push(Condition::has_id([SCHEMA_POINT.to_string()]));
 
+        let mut offset = None;
+        loop {
+            let page = self
+                .qdrant
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection.clone(),
+                    filter: Some(filter.clone()),
+                    offset: offset.take(),
+                    limit: Some(SCROLL_PAGE),
+                    with_payload: Some(true.into()),
+                    ..Default::default()
+                })
+                .await?;
+
+            for point in page.result {
+                visit(point_key(&point.id), point.payload);
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn export_points(
+        &self,
+        mut visit: impl FnMut(ExportedPoint) -> Result<()>,
+    ) -> Result<()> {
+        let mut filter = self.scope().unwrap_or_default();
+        filter
+            .must_not
+            .push(Condition::has_id([SCHEMA_POINT.to_string()]));
+
+        let mut offset = None;
+        loop {
+            let page = self
+                .qdrant
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection.clone(),
+                    filter: Some(filter.clone()),
+                    offset: offset.take(),
+                    limit: Some(SCROLL_PAGE),
+                    with_payload: Some(true.into()),
+                    with_vectors: Some(true.into()),
+                    ..Default::default()
+                })
+                .await?;
+
+            for mut point in page.result {
+                let mut exported = ExportedPoint {
+                    id: point_key(&point.id),
+                    vectors: HashMap::new(),
+                    sparse: None,
+                    payload: std::mem::take(&mut point.payload),
+                };
+                exported.payload.remove(NAMESPACE_KEY);
+                // derived from the sparse vector again on import
+                exported.payload.remove(TERMS_KEY);
+
+                let vectors = match point.vectors.and_then(|x| x.vectors_options) {
+                    Some(VectorsOptions::Vectors(named)) => named.vectors,
+                    _ => HashMap::new(),
+                };
+                for (name, vector) in vectors {
+                    match vector.indices {
+                        Some(indices) => {
+                            exported.sparse =
+                                Some(indices.data.into_iter().zip(vector.data).collect())
+                        }
+                        None => {
+                            exported.vectors.insert(name, vector.data);
+                        }
+                    }
+                }
+
+                visit(exported)?;
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Upserts the points in one batch, retrying it like [`Qdrant::insert_docs`] does.
+    async fn import_points(&self, points: Vec<ExportedPoint>) -> Result<()> {
+        let points = points
+            .into_iter()
+            .map(|point| {
+                let mut vectors: HashMap<String, Vector> = point
+                    .vectors
+                    .into_iter()
+                    .map(|(name, vector)| (name, vector.into()))
+                    .collect();
+                if let Some(sparse) = &point.sparse {
+                    vectors.insert(LEXICAL_VECTOR.to_string(), sparse.as_slice().into());
+                }
+
+                let mut payload = point.payload;
+                payload.remove(NAMESPACE_KEY);
+                if let Some(tenant) = &self.tenant {
+                    payload.insert(NAMESPACE_KEY.to_string(), tenant.clone().into());
+                }
+                if let Some(sparse) = &point.sparse {
+                    payload.insert(TERMS_KEY.to_string(), terms(sparse));
+                }
+
+                PointStruct {
+                    id: Some(point_id(&point.id)),
+                    vectors: Some(vectors.into()),
+                    payload,
+                }
+            })
+            .collect();
+
+        self.upsert_batch(points).await
+    }
+
+    /// Re-embeds every document into a new collection, then points the alias at it in a single
+    /// step, so searches see either the old or the new collection. The old collection is deleted
+    /// unless `keep_old` is set.
+    ///
+    /// A collection from before aliases were used has to be deleted before the alias can take its
+    /// name, so it is never kept.
+    async fn migrate(
+        &self,
+        embedder: &Embedder,
+        keep_old: bool,
+        progress: impl Fn(usize),
+    ) -> Result<Migration> {
+        let from = self.existing_collection().await?;
+        let to = self.versioned_name()?;
+        self.create_collection(&to, embedder).await?;
+        self.copy_commits(&from, &to).await?;
+
+        let mut offset = None;
+        let mut documents = 0;
+        let mut lengths = DocumentLengths::default();
+        loop {
+            let page = self
+                .qdrant
+                .scroll(&ScrollPoints {
+                    collection_name: from.clone(),
+                    filter: Some(Filter::must_not([Condition::has_id([
+                        SCHEMA_POINT.to_string()
+                    ])])),
+                    offset: offset.take(),
+                    limit: Some(MIGRATION_PAGE),
+                    with_payload: Some(true.into()),
+                    with_vectors: Some(false.into()),
+                    ..Default::default()
+                })
+                .await?;
+
+            let mut ids = Vec::new();
+            let mut kept = Vec::new();
+            let mut docs = Vec::new();
+            for mut point in page.result {
+                let preserved: HashMap<String, Value> = PRESERVED_KEYS
+                    .iter()
+                    .filter_map(|key| Some((key.to_string(), point.payload.remove(*key)?)))
+                    .collect();
+
+                ids.push(point.id);
+                kept.push(preserved);
+                docs.push(into_document(point.payload)?);
+            }
+
+            let mut points = Vec::new();
+            let embeddings = embedder.embed_documents(docs)?;
+            let sparse =
+                lengths.weigh(embeddings.iter().map(|embedding| embedding.sparse.as_ref()));
+            let embeddings = embeddings.iter().zip(sparse);
+            for ((id, preserved), (embedding, sparse)) in ids.into_iter().zip(kept).zip(embeddings)
+            {
+                let mut point = self.point(embedding, sparse)?;
+                point.id = id.or(point.id);
+                point.payload.extend(preserved);
+                points.push(point);
+            }
+
+            documents += points.len();
+            if !points.is_empty() {
+                self.qdrant
+                    .upsert_points_blocking(&to, None, points, None)
+                    .await?;
+            }
+            progress(documents);
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        self.set_lengths(&to, lengths).await?;
+        self.switch_alias(Some(&from), &to, keep_old).await?;
+
+        Ok(Migration {
+            from,
+            to,
+            documents,
+        })
+    }
+
+    /// Stores documents in batches, several at a time, retrying batches that fail for transient
+    /// reasons. A batch that still fails is reported rather than stopping the rest.
+    async fn insert_docs(
+        &self,
+        docs: Vec<Embedding>,
+        progress: impl Fn(InsertProgress),
+    ) -> Result<InsertReport> {
+        let lexical = self.lexical_lengths().await?;
+        let sparse = match lexical {
+            Some(mut lengths) => {
+                let sparse = lengths.weigh(docs.iter().map(|embedding| embedding.sparse.as_ref()));
+                self.set_lengths(&self.collection, lengths).await?;
+                sparse
+            }
+            // collections indexed without lexical vectors have no room for them
+            None => vec![None; docs.len()],
+        };
+        let points = docs
+            .iter()
+            .zip(sparse)
+            .map(|(embedding, sparse)| self.point(embedding, sparse))
+            .collect::<Result<Vec<_>>>()?;
+        let total = points.len();
+        let batches: Vec<Vec<PointStruct>> = points
+            .into_iter()
+            .chunks(self.upsert.batch_size.max(1))
+            .into_iter()
+            .map(Iterator::collect)
+            .collect();
+
+        let mut upserts = stream::iter(batches.into_iter().enumerate())
+            .map(|(batch, points)| async move {
+                let len = points.len();
+                (batch, len, self.upsert_batch(points).await)
+            })
+            .buffer_unordered(self.upsert.concurrency.max(1));
+
+        let mut report = InsertReport::default();
+        while let Some((batch, points, res)) = upserts.next().await {
+            match res {
+                Ok(()) => report.inserted += points,
+                Err(err) => report.failures.push(BatchFailure {
+                    batch,
+                    points,
+                    error: err.to_string(),
+                }),
+            }
+            progress(InsertProgress {
+                inserted: report.inserted,
+                failed: report.failed(),
+                total,
+            });
+        }
+        report.failures.sort_by_key(|failure| failure.batch);
+
+        Ok(report)
+    }
+
+    /// The commit the code of `repo` was last fully synced at, as recorded on the schema point.
+    async fn indexed_commit(&self, repo: &str) -> Result<Option<String>> {
+        let key = commit_key(self.tenant.as_deref(), repo);
+        let res = self
+            .qdrant
+            .get_points(
+                &self.collection,
+                None,
+                &[SCHEMA_POINT.to_string().into()],
+                Some(false),
+                Some(vec![key.as_str()]),
+                None,
+            )
+            .await?;
+
+        let commit = res
+            .result
+            .into_iter()
+            .next()
+            .and_then(|mut point| point.payload.remove(&key))
+            .and_then(|commit| String::try_from(commit).ok());
+
+        Ok(commit)
+    }
+
+    /// Records on the schema point that the code of `repo` is synced at `commit`.
+    async fn set_commit(&self, repo: &str, commit: &str) -> Result<()> {
+        let mut payload = Payload::new();
+        payload.insert(commit_key(self.tenant.as_deref(), repo), commit);
+
+        self.qdrant
+            .set_payload_blocking(
+                &self.collection,
+                None,
+                &vec![SCHEMA_POINT.to_string().into()].into(),
+                payload,
+                None,
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the code points of `repo`, only those from `files` if given.
+    async fn delete_code(&self, repo: &str, files: Option<Vec<String>>) -> Result<()> {
+        let mut filter = self.repo_code_filter(repo);
+        let all = files.is_none();
+        if let Some(files) = files {
+            filter
+                .must
+                .push(Condition::matches("data.context.file_path", files));
+        }
+
+        self.qdrant
+            .delete_points_blocking(&self.collection, None, &filter.into(), None)
+            .await?;
+
+        if all {
             self.qdrant
-                .upsert_points(COLLECTION_NAME.to_string(), None, vec![point], None)
+                .delete_payload_blocking(
+                    &self.collection,
+                    None,
+                    &vec![SCHEMA_POINT.to_string().into()].into(),
+                    vec![commit_key(self.tenant.as_deref(), repo)],
+                    None,
+                )
                 .await?;
         }
 
         Ok(())
     }
 
-    pub async fn search(&self, embedding: CombinedEmbedding) -> Result<Vec<TCode>> {
-        let search_points = vec![
-            SearchPoints {
-                collection_name: COLLECTION_NAME.into(),
-                vector: embedding.0,
-                limit: 4,
-                with_payload: Some(true.into()),
-                vector_name: Some("code".to_string()),
+    async fn delete(&self, selection: &Selection) -> Result<u64> {
+        check_selection(selection)?;
+        let filter = self.scoped(selection.to_qdrant());
+
+        let count = self
+            .qdrant
+            .count(&CountPoints {
+                collection_name: self.collection.clone(),
+                filter: Some(filter.clone()),
+                exact: Some(true),
                 ..Default::default()
-            },
-            SearchPoints {
-                collection_name: COLLECTION_NAME.into(),
-                vector: embedding.1,
-                limit: 4,
+            })
+            .await?;
+
+        self.qdrant
+            .delete_points_blocking(&self.collection, None, &filter.into(), None)
+            .await?;
+
+        Ok(count.result.map_or(0, |result| result.count))
+    }
+
+    async fn indexed_files(&self, repo: Option<&str>) -> Result<BTreeSet<String>> {
+        let selection = Selection {
+            kinds: vec![DocumentKind::Code],
+            repo: repo.map(ToString::to_string),
+            without_repo: repo.is_none(),
+            ..Default::default()
+        };
+        let filter = self.scoped(selection.to_qdrant());
+
+        let mut files = BTreeSet::new();
+        let mut offset = None;
+        loop {
+            let page = self
+                .qdrant
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection.clone(),
+                    filter: Some(filter.clone()),
+                    offset: offset.take(),
+                    limit: Some(SCROLL_PAGE),
+                    with_payload: Some(vec!["files"].into()),
+                    ..Default::default()
+                })
+                .await?;
+
+            for point in page.result {
+                if let Some(list) = point.payload.get("files").and_then(Value::as_list) {
+                    files.extend(list.iter().filter_map(Value::as_str).cloned());
+                }
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Searches each vector for `options.limit` points, then fuses the results into a single
+    /// ranking of at most that many.
+    ///
+    /// A `file` glob is partly checked after searching, so fewer results may be returned.
+    async fn search(
+        &self,
+        embedding: CombinedEmbedding,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let mode = options.mode;
+        let limit = options.candidates();
+        let filter = match options.filter.to_qdrant() {
+            Some(filter) => Some(self.scoped(filter)),
+            None => self.scope(),
+        };
+        let file_matcher = options.filter.file_matcher()?;
+        let mut search_points = Vec::new();
+
+        if mode != SearchMode::Sparse {
+            search_points.extend(
+                embedding
+                    .dense
+                    .into_iter()
+                    .map(|(name, vector)| SearchPoints {
+                        collection_name: self.collection.clone(),
+                        vector,
+                        limit,
+                        filter: filter.clone(),
+                        with_payload: Some(true.into()),
+                        vector_name: Some(name),
+                        params: search_params(options),
+                        ..Default::default()
+                    }),
+            );
+        }
+
+        if mode != SearchMode::Dense {
+            // collections indexed without lexical vectors are searched with the dense ones only
+            let sparse = match embedding.sparse {
+                Some(sparse) if self.lexical_lengths().await?.is_some() => Some(sparse),
+                _ => None,
+            };
+            if let Some(sparse) = sparse {
+                let sparse = self.with_idf(sparse).await?;
+                let (indices, values): (Vec<u32>, Vec<f32>) = sparse.into_iter().unzip();
+                search_points.push(SearchPoints {
+                    collection_name: self.collection.clone(),
+                    vector: values,
+                    sparse_indices: Some(SparseIndices { data: indices }),
+                    limit,
+                    filter,
+                    with_payload: Some(true.into()),
+                    vector_name: Some(LEXICAL_VECTOR.to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if search_points.is_empty() {
+            return Err(anyhow!(
+                "there are no vectors to search with in {mode:?} mode"
+            ));
+        }
+
+        let names: Vec<String> = search_points
+            .iter()
+            .map(|search| search.vector_name.clone().unwrap_or_default())
+            .collect();
+
+        let lists = match options.group_by {
+            Some(group_by) => self.search_groups(search_points, group_by, options).await?,
+            None => {
+                let batch_results = self
+                    .qdrant
+                    .search_batch_points(&SearchBatchPoints {
+                        collection_name: self.collection.clone(),
+                        search_points,
+                        ..Default::default()
+                    })
+                    .await?;
+
+                batch_results
+                    .result
+                    .into_iter()
+                    .map(|batch| hits(batch.result))
+                    .collect()
+            }
+        };
+
+        let mut results = options.fuse(names.into_iter().zip(lists).collect())?;
+        if let Some(matcher) = file_matcher {
+            results.retain(|result| matcher.matches(&result.document));
+        }
+
+        Ok(options.page(results))
+    }
+
+    /// The indexed points `seed` refers to, closest match first, with their `vector`.
+    ///
+    /// Ranges and symbols are looked up in `repo` if given.
+    async fn lookup(
+        &self,
+        seed: &Seed,
+        vector: &str,
+        repo: Option<&str>,
+    ) -> Result<Vec<SeedPoint>> {
+        let mut filter = self.scoped(seed.filter());
+        if let Some(repo) = repo {
+            filter
+                .must
+                .push(Condition::matches("repo", repo.to_string()));
+        }
+
+        let res = self
+            .qdrant
+            .scroll(&ScrollPoints {
+                collection_name: self.collection.clone(),
+                filter: Some(filter),
+                limit: Some(SEED_LIMIT),
                 with_payload: Some(true.into()),
-                vector_name: Some("nlp".to_string()),
+                with_vectors: Some(vec![vector].into()),
                 ..Default::default()
-            },
-        ];
-        let batch_results = self
+            })
+            .await?;
+
+        let mut points = Vec::new();
+        for point in res.result {
+            let vector = match point.vectors.and_then(|x| x.vectors_options) {
+                Some(VectorsOptions::Vectors(mut named)) => named.vectors.remove(vector),
+                Some(VectorsOptions::Vector(unnamed)) => Some(unnamed),
+                None => None,
+            }
+            .ok_or_else(|| anyhow!("point has no `{vector}` vector"))?;
+
+            points.push(SeedPoint {
+                id: point_key(&point.id),
+                vector: vector.data,
+                document: into_document(point.payload)?,
+            });
+        }
+
+        points.sort_by_key(|point| std::cmp::Reverse(seed.overlap(&point.document)));
+
+        Ok(points)
+    }
+
+    /// The nearest neighbours of `embedding` by `vector`, leaving out the points in `exclude`.
+    async fn similar(
+        &self,
+        vector: &str,
+        embedding: Vec<f32>,
+        exclude: &[String],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let mut filter = self.scoped(options.filter.to_qdrant().unwrap_or_default());
+        if !exclude.is_empty() {
+            let ids = exclude.iter().map(|id| point_id(id));
+            filter.must_not.push(Condition::has_id(ids));
+        }
+        let file_matcher = options.filter.file_matcher()?;
+
+        let res = self
             .qdrant
-            .search_batch_points(&SearchBatchPoints {
-                collection_name: COLLECTION_NAME.into(),
-                search_points,
+            .search_points(&SearchPoints {
+                collection_name: self.collection.clone(),
+                vector: embedding,
+                limit: options.candidates(),
+                filter: Some(filter),
+                with_payload: Some(true.into()),
+                vector_name: Some(vector.to_string()),
+                params: search_params(options),
                 ..Default::default()
             })
             .await?;
 
-        let mut final_response = Vec::new();
+        let mut results = Vec::new();
+        for point in res.result {
+            let document = into_document(point.payload)?;
+            if file_matcher
+                .as_ref()
+                .is_some_and(|matcher| !matcher.matches(&document))
+            {
+                continue;
+            }
 
-        for result in batch_results.result {
-            let mut search_result = result
-                .result
-                .into_iter()
-                .map(|x| {
-                    let payload = x.payload.get("data").unwrap().to_owned();
-                    serde_json::from_value::<TCode>(payload.into()).unwrap()
-                })
-                .collect::<Vec<TCode>>();
+            results.push(SearchResult {
+                id: point_key(&point.id),
+                scores: HashMap::from([(vector.to_string(), point.score)]),
+                score: point.score,
+                rerank_score: None,
+                group: None,
+                document,
+            });
+        }
+
+        Ok(options.page(results))
+    }
+}
+
+/// The term indices of a sparse vector, as stored under [`TERMS_KEY`].
+fn terms(sparse: &SparseVector) -> Value {
+    sparse
+        .iter()
+        .map(|(index, _)| *index as i64)
+        .collect::<Vec<i64>>()
+        .into()
+}
+
+fn hits(points: Vec<ScoredPoint>) -> Vec<Hit> {
+    points
+        .into_iter()
+        .map(|point| Hit {
+            id: point_key(&point.id),
+            score: point.score,
+            payload: point.payload,
+        })
+        .collect()
+}
+
+fn point_key(id: &Option<PointId>) -> String {
+    match id.as_ref().and_then(|id| id.point_id_options.as_ref()) {
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        Some(PointIdOptions::Uuid(uuid)) => uuid.clone(),
+        None => String::new(),
+    }
+}
 
-            final_response.append(&mut search_result);
+impl RestClient {
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.api_key {
+            Some(api_key) => request.set("api-key", api_key),
+            None => request,
         }
-        Ok(final_response)
     }
 }
 
-fn collection_params() -> VectorParamsMap {
-    let mut map: HashMap<String, VectorParams> = HashMap::new();
-    map.insert(
-        "nlp".to_string(),
-        VectorParams {
-            size: 384,
-            distance: Distance::Cosine as i32,
-            ..Default::default()
-        },
-    );
-
-    map.insert(
-        "code".to_string(),
-        VectorParams {
-            size: 768,
-            distance: Distance::Cosine as i32,
-            ..Default::default()
-        },
-    );
+impl From<SnapshotDescription> for Snapshot {
+    fn from(description: SnapshotDescription) -> Self {
+        Self {
+            name: description.name,
+            created_at: description.creation_time.map(|time| time.seconds),
+            size: description.size.max(0) as u64,
+            checksum: description.checksum,
+        }
+    }
+}
+
+/// Whether a request may succeed if it is sent again.
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Status>().is_some_and(|status| {
+        matches!(
+            status.code(),
+            Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+        )
+    })
+}
+
+fn collection_params(embedder: &Embedder, index: &IndexConfig) -> VectorParamsMap {
+    let map = embedder
+        .vectors()
+        .map(|(name, dimension)| {
+            let params = VectorParams {
+                size: dimension as u64,
+                distance: Distance::Cosine as i32,
+                on_disk: index.vectors_on_disk.then_some(true),
+                ..Default::default()
+            };
+            (name.to_string(), params)
+        })
+        .collect();
 
     VectorParamsMap { map }
 }
+
+/// The HNSW parameters that differ from Qdrant's defaults, if any do.
+fn hnsw_config(index: &IndexConfig) -> Option<HnswConfigDiff> {
+    let on_disk = index.hnsw_on_disk.then_some(true);
+    if index.m.is_none() && index.ef_construct.is_none() && on_disk.is_none() {
+        return None;
+    }
+
+    Some(HnswConfigDiff {
+        m: index.m,
+        ef_construct: index.ef_construct,
+        on_disk,
+        ..Default::default()
+    })
+}
+
+fn quantization_config(quantization: &Quantization) -> QuantizationConfig {
+    let quantization = match *quantization {
+        Quantization::Scalar {
+            quantile,
+            always_ram,
+        } => quantization_config::Quantization::Scalar(ScalarQuantization {
+            r#type: QuantizationType::Int8 as i32,
+            quantile,
+            always_ram: Some(always_ram),
+        }),
+        Quantization::Product {
+            compression,
+            always_ram,
+        } => {
+            let compression = match compression {
+                Compression::X4 => CompressionRatio::X4,
+                Compression::X8 => CompressionRatio::X8,
+                Compression::X16 => CompressionRatio::X16,
+                Compression::X32 => CompressionRatio::X32,
+                Compression::X64 => CompressionRatio::X64,
+            };
+            quantization_config::Quantization::Product(ProductQuantization {
+                compression: compression as i32,
+                always_ram: Some(always_ram),
+            })
+        }
+        Quantization::Binary { always_ram } => {
+            quantization_config::Quantization::Binary(BinaryQuantization {
+                always_ram: Some(always_ram),
+            })
+        }
+    };
+
+    QuantizationConfig {
+        quantization: Some(quantization),
+    }
+}
+
+/// How searches treat quantized vectors, unless Qdrant's defaults are kept.
+fn search_params(options: &SearchOptions) -> Option<SearchParams> {
+    if options.rescore.is_none() && options.oversampling.is_none() {
+        return None;
+    }
+
+    Some(SearchParams {
+        quantization: Some(QuantizationSearchParams {
+            rescore: options.rescore,
+            oversampling: options.oversampling,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
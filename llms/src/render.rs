@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+
+use parser::discussion::{symbol_name, TDiscussion};
+use parser::{Document, DocumentKind, TCode, TContext};
+
+use crate::config::TemplatesConfig;
+
+/// Turns documents into the text embedded for each vector.
+///
+/// Templates are plain text with `{placeholder}`s, picked by vector name and document kind
+/// (`code`, `issue`, `pull_request`, `review_comment`). Configured templates take precedence;
+/// otherwise the `code` vector gets the source, the `lexical` sparse vector and the `rerank`
+/// stage get names and source, and every other vector gets a prose description.
+///
+/// Code placeholders: `name`, `qualified_name`, `kind`, `signature`, `code`, `docs`, `module`,
+/// `file`, `repo`. Discussion placeholders: `kind`, `number`, `title`, `body`, `comments`,
+/// `snippet`, `files`, `symbols`, `state`, `author`, `repo`.
+pub struct Renderer {
+    version: u32,
+    templates: BTreeMap<String, HashMap<String, String>>,
+}
+
+impl Renderer {
+    pub fn new(config: &TemplatesConfig) -> Self {
+        Self {
+            version: config.version,
+            templates: config.vectors.clone(),
+        }
+    }
+
+    /// The version of the templates, stored with every point so that points rendered with older
+    /// templates can be found and re-embedded.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn render(&self, vector: &str, document: &Document) -> String {
+        let kind = document.kind();
+        let template = self
+            .templates
+            .get(vector)
+            .and_then(|templates| templates.get(&kind.to_string()))
+            .map(String::as_str)
+            .unwrap_or_else(|| default_template(vector, kind));
+
+        let fields = match document {
+            Document::Code(code) => code_fields(code),
+            Document::Discussion(discussion) => discussion_fields(discussion),
+        };
+
+        fill(template, &fields)
+    }
+}
+
+fn default_template(vector: &str, kind: DocumentKind) -> &'static str {
+    match (vector, kind) {
+        ("code", DocumentKind::Code) => "{code}",
+        ("code", DocumentKind::ReviewComment) => "{snippet}\n{body}",
+        ("code", _) => "{title}\n{body}",
+        ("lexical" | "rerank", DocumentKind::Code) => "{qualified_name}\n{file}\n{docs}\n{code}",
+        ("lexical" | "rerank", _) => "{title}\n{body}\n{comments}\n{snippet}\n{files}\n{symbols}",
+        (_, DocumentKind::Code) => "{kind} {qualified_name} in module {module} ({file})\n{docs}",
+        (_, DocumentKind::ReviewComment) => "Review comment on #{number} {title}\n{body}",
+        (_, _) => "{kind} #{number}: {title}\n{body}\n{comments}",
+    }
+}
+
+fn code_fields(code: &TCode) -> HashMap<&'static str, String> {
+    let context = |field: fn(&TContext) -> &Option<String>| {
+        code.context
+            .as_ref()
+            .and_then(|ctx| field(ctx).clone())
+            .unwrap_or_default()
+    };
+    let snippet = context(|ctx| &ctx.snippet);
+
+    HashMap::from([
+        ("name", code.name.clone()),
+        ("qualified_name", symbol_name(code)),
+        ("kind", code.code_type.to_string()),
+        ("signature", code.signature.clone()),
+        (
+            "code",
+            if snippet.is_empty() {
+                code.signature.clone()
+            } else {
+                snippet
+            },
+        ),
+        (
+            "docs",
+            code.docstring.as_deref().map(doc_text).unwrap_or_default(),
+        ),
+        ("module", context(|ctx| &ctx.module)),
+        ("file", context(|ctx| &ctx.file_path)),
+        ("repo", context(|ctx| &ctx.repository)),
+    ])
+}
+
+fn discussion_fields(discussion: &TDiscussion) -> HashMap<&'static str, String> {
+    HashMap::from([
+        ("kind", discussion.kind.to_string()),
+        ("number", discussion.number.to_string()),
+        ("title", discussion.title.clone()),
+        ("body", discussion.body.clone().unwrap_or_default()),
+        ("comments", discussion.comments.join("\n")),
+        ("snippet", discussion.snippet.clone().unwrap_or_default()),
+        ("files", discussion.files.join(", ")),
+        ("symbols", discussion.symbols.join(", ")),
+        ("state", discussion.state.clone().unwrap_or_default()),
+        ("author", discussion.author.clone().unwrap_or_default()),
+        ("repo", discussion.repo.clone()),
+    ])
+}
+
+/// Docstrings are stored as the attribute's tokens (`# [doc = " Text"]`); this is just the text.
+fn doc_text(docstring: &str) -> String {
+    let text = docstring
+        .trim()
+        .strip_prefix("# [doc =")
+        .and_then(|doc| doc.strip_suffix(']'))
+        .unwrap_or(docstring)
+        .trim();
+
+    text.trim_matches('"').trim().replace("\\n", "\n")
+}
+
+/// Fills in the template line by line, dropping lines that only held empty fields.
+fn fill(template: &str, fields: &HashMap<&'static str, String>) -> String {
+    let mut lines = Vec::new();
+    for line in template.lines() {
+        let text = fill_line(line, fields);
+
+        if line.trim().is_empty() || !text.trim().is_empty() {
+            lines.push(text);
+        }
+    }
+
+    lines.join("\n").trim_end().to_string()
+}
+
+/// Replaces the placeholders of a line in a single pass, so that text inserted for one field
+/// (e.g. code with `format!("{name}")` in it) is never taken for a placeholder itself. Braces
+/// that don't hold a known field are kept as they are.
+fn fill_line(line: &str, fields: &HashMap<&'static str, String>) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let field = after
+            .find('}')
+            .and_then(|end| Some((fields.get(&after[..end])?, end)));
+        match field {
+            Some((value, end)) => {
+                text.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+
+    text
+}
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Result};
+use ndarray::{s, Array};
+use ort::{GraphOptimizationLevel, Session, Value};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use crate::config::{Config, RerankConfig};
+use crate::models::ModelFiles;
+use crate::render::Renderer;
+use crate::store::{SearchOptions, SearchResult};
+
+/// The template name documents are rendered with for reranking.
+const RERANK_TEMPLATE: &str = "rerank";
+
+/// A second ranking stage: a cross-encoder reads the prompt together with each candidate and
+/// scores how well they match, which is slower but more precise than comparing vectors.
+pub struct Reranker {
+    encoder: CrossEncoder,
+    renderer: Renderer,
+    candidates: u64,
+    top_k: usize,
+}
+
+impl Reranker {
+    /// The configured reranker, or `None` if reranking is disabled.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        if !config.rerank.enabled {
+            return Ok(None);
+        }
+
+        let files = ModelFiles::new(&config.models)?;
+        Ok(Some(Self {
+            encoder: CrossEncoder::new(&config.rerank, &files)?,
+            renderer: Renderer::new(&config.templates),
+            candidates: config.rerank.candidates,
+            top_k: config.rerank.top_k,
+        }))
+    }
+
+    /// How many results to retrieve for reranking.
+    pub fn candidates(&self) -> u64 {
+        self.candidates
+    }
+
+    pub fn top_k(&self) -> usize {
+        self.top_k
+    }
+
+    /// The options to retrieve candidates with for the page `options` asks for. Ungrouped results
+    /// are fetched from the start, `candidates` more than the page skips (the configured number by
+    /// default), as the page can only be cut once they're reranked. Groups keep their order, so
+    /// they're paged as usual.
+    pub fn candidate_options(
+        &self,
+        options: &SearchOptions,
+        candidates: Option<u64>,
+    ) -> SearchOptions {
+        let mut options = options.clone();
+        if options.group_by.is_none() {
+            options.limit = options.offset + candidates.unwrap_or(self.candidates);
+            options.offset = 0;
+        }
+
+        options
+    }
+
+    /// Reranks the results of a search made with [`Reranker::candidate_options`], then cuts the
+    /// page `options` asks for, keeping `top_k` results. Grouped results are reordered within each
+    /// group instead, and all of them kept.
+    pub fn rerank_page(
+        &self,
+        prompt: &str,
+        results: Vec<SearchResult>,
+        options: &SearchOptions,
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        if options.group_by.is_some() {
+            let mut reranked = Vec::with_capacity(results.len());
+            for group in results.chunk_by(|a, b| a.group == b.group) {
+                reranked.extend(self.rerank(prompt, group.to_vec(), group.len())?);
+            }
+            return Ok(reranked);
+        }
+
+        let offset = options.offset as usize;
+        let reranked = self.rerank(prompt, results, offset + top_k)?;
+        Ok(reranked.into_iter().skip(offset).collect())
+    }
+
+    /// Orders `results` by their relevance to `prompt`, keeping the best `top_k`.
+    pub fn rerank(
+        &self,
+        prompt: &str,
+        results: Vec<SearchResult>,
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let texts: Vec<String> = results
+            .iter()
+            .map(|result| self.renderer.render(RERANK_TEMPLATE, &result.document))
+            .collect();
+        let scores = self.encoder.score(prompt, &texts)?;
+
+        let mut scored: Vec<(f32, SearchResult)> = scores.into_iter().zip(results).collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scored
+            .into_iter()
+            .take(top_k)
+            .map(|(score, result)| SearchResult {
+                rerank_score: Some(score),
+                ..result
+            })
+            .collect())
+    }
+}
+
+/// An ONNX cross-encoder such as `BAAI/bge-reranker-base`, taking `(query, passage)` pairs and
+/// returning one logit per pair.
+struct CrossEncoder {
+    tokenizer: Tokenizer,
+    session: Session,
+    needs_token_type_ids: bool,
+}
+
+impl CrossEncoder {
+    fn new(config: &RerankConfig, files: &ModelFiles) -> Result<Self> {
+        let repo = files.repo(&config.repo, &config.revision);
+
+        let mut tokenizer =
+            Tokenizer::from_file(repo.get("tokenizer.json")?).map_err(anyhow::Error::msg)?;
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_length,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+        if tokenizer.get_padding().is_none() {
+            let (pad_token, pad_id) = ["<pad>", "[PAD]"]
+                .into_iter()
+                .find_map(|token| Some((token.to_string(), tokenizer.token_to_id(token)?)))
+                .ok_or_else(|| anyhow!("{} has no padding token", config.repo))?;
+            tokenizer.with_padding(Some(PaddingParams {
+                pad_token,
+                pad_id,
+                ..Default::default()
+            }));
+        }
+
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(repo.get(&config.onnx_file)?)?;
+        let needs_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+
+        Ok(Self {
+            tokenizer,
+            session,
+            needs_token_type_ids,
+        })
+    }
+
+    fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let inputs = passages
+            .iter()
+            .map(|passage| (query, passage.as_str()))
+            .collect();
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs, true)
+            .map_err(anyhow::Error::msg)?;
+
+        let shape = (encodings.len(), encodings[0].len());
+        let array = |values: Vec<i64>| Array::from_shape_vec(shape, values);
+        let ids = array(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_ids().iter().map(|&x| x as i64))
+                .collect(),
+        )?;
+        let mask = array(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_attention_mask().iter().map(|&x| x as i64))
+                .collect(),
+        )?;
+        let type_ids = array(
+            encodings
+                .iter()
+                .flat_map(|e| e.get_type_ids().iter().map(|&x| x as i64))
+                .collect(),
+        )?;
+
+        let mut session_inputs = ort::inputs![
+            "input_ids" => Value::from_array(ids)?,
+            "attention_mask" => Value::from_array(mask)?,
+        ]?;
+        if self.needs_token_type_ids {
+            session_inputs.push(("token_type_ids".into(), Value::from_array(type_ids)?.into()));
+        }
+
+        let outputs = self.session.run(session_inputs)?;
+        let logits = outputs["logits"].try_extract_tensor::<f32>()?;
+
+        Ok(logits.slice(s![.., 0]).iter().copied().collect())
+    }
+}
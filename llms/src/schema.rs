@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Embedder;
+
+/// The version of the payload layout, bumped whenever points need re-indexing to be found by
+/// the current code (e.g. new filter keys).
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// What a collection's points were indexed with, stored alongside them so that searching with
+/// different models is caught instead of returning nonsense.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub version: u32,
+    pub vectors: BTreeMap<String, VectorSchema>,
+    pub sparse: bool,
+    pub template_version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorSchema {
+    pub model: String,
+    /// The query encoder, for models with separate query and document encoders.
+    #[serde(default)]
+    pub query_model: Option<String>,
+    pub dimension: usize,
+}
+
+impl Schema {
+    /// The schema of documents embedded by `embedder`.
+    pub fn new(embedder: &Embedder) -> Self {
+        let vectors = embedder
+            .models()
+            .map(|(name, model)| {
+                let (passage, query) = model.model_names();
+                let schema = VectorSchema {
+                    model: passage.to_string(),
+                    query_model: query.map(ToString::to_string),
+                    dimension: model.dimension(),
+                };
+                (name.to_string(), schema)
+            })
+            .collect();
+
+        Self {
+            version: SCHEMA_VERSION,
+            vectors,
+            sparse: embedder.has_sparse(),
+            template_version: embedder.template_version(),
+        }
+    }
+
+    /// Why points indexed with this schema can't be searched with `current`, if they can't.
+    pub fn conflicts(&self, current: &Schema) -> Vec<String> {
+        let mut conflicts = Vec::new();
+
+        for (name, vector) in &current.vectors {
+            match self.vectors.get(name) {
+                None => conflicts.push(format!("there is no `{name}` vector")),
+                Some(indexed) if indexed != vector => conflicts.push(format!(
+                    "`{name}` was embedded with {} ({} dimensions), but {} ({} dimensions) is configured",
+                    indexed.model, indexed.dimension, vector.model, vector.dimension
+                )),
+                Some(_) => {}
+            }
+        }
+
+        conflicts
+    }
+
+    /// Why points indexed with this schema should be re-indexed, even though they can still be
+    /// searched with `current`.
+    pub fn outdated(&self, current: &Schema) -> Vec<String> {
+        let mut reasons = Vec::new();
+
+        if self.version != current.version {
+            reasons.push(format!(
+                "the payload layout is version {}, but the current one is {}",
+                self.version, current.version
+            ));
+        }
+        if self.template_version != current.template_version {
+            reasons.push(format!(
+                "documents were rendered with templates version {}, but the current one is {}",
+                self.template_version, current.template_version
+            ));
+        }
+        if current.sparse && !self.sparse {
+            reasons.push(
+                "there is no lexical sparse vector, so searches only use the dense ones"
+                    .to_string(),
+            );
+        }
+
+        reasons
+    }
+}
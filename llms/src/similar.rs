@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use parser::Document;
+use qdrant_client::qdrant::{Condition, Filter, PointId, Range, Value};
+
+use crate::filter::has_keyword;
+
+/// What a "find similar" search starts from, parsed from one of:
+///
+/// - a point ID, as search results are returned with
+/// - `file:line_from-line_to` (or `file:line`), with the file relative to the repository root
+/// - anything else is taken as a symbol name, such as `process_dir` or `Qdrant::search`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Seed {
+    Point(String),
+    Range {
+        file: String,
+        from: usize,
+        to: usize,
+    },
+    Symbol(String),
+}
+
+impl FromStr for Seed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            bail!("expected a point ID, `file:line_from-line_to` or a symbol name");
+        }
+
+        if let Some((file, lines)) = s.rsplit_once(':') {
+            let (from, to) = lines.split_once('-').unwrap_or((lines, lines));
+            if let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) {
+                if from == 0 || from > to {
+                    bail!("invalid line range {lines}");
+                }
+                let file = file.to_string();
+                return Ok(Self::Range { file, from, to });
+            }
+        }
+
+        if uuid::Uuid::parse_str(s).is_ok() || s.parse::<u64>().is_ok() {
+            return Ok(Self::Point(s.to_string()));
+        }
+
+        Ok(Self::Symbol(s.to_string()))
+    }
+}
+
+impl fmt::Display for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Point(id) => write!(f, "{id}"),
+            Self::Range { file, from, to } => write!(f, "{file}:{from}-{to}"),
+            Self::Symbol(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Seed {
+    /// The points the seed refers to.
+    pub(crate) fn filter(&self) -> Filter {
+        match self {
+            Self::Point(id) => Filter::must([Condition::has_id([point_id(id)])]),
+            Self::Range { file, from, to } => Filter::must([
+                Condition::matches("files", file.clone()),
+                Condition::range(
+                    "data.line_from",
+                    Range {
+                        lte: Some(*to as f64),
+                        ..Default::default()
+                    },
+                ),
+                Condition::range(
+                    "data.line_to",
+                    Range {
+                        gte: Some(*from as f64),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            Self::Symbol(name) => Filter::must([Condition::matches("symbol", name.clone())]),
+        }
+    }
+
+    /// Whether a point is one the seed refers to, the way Qdrant would apply [`Seed::filter`].
+    pub(crate) fn matches(&self, id: &str, payload: &HashMap<String, Value>) -> bool {
+        let line = |key| {
+            let value = payload.get("data")?.get_struct(key).ok()?;
+            value.as_integer().or(value.as_double().map(|x| x as i64))
+        };
+
+        match self {
+            Self::Point(point) => point == id,
+            Self::Range { file, from, to } => {
+                has_keyword(payload, "files", file)
+                    && line("line_from").is_some_and(|line| line <= *to as i64)
+                    && line("line_to").is_some_and(|line| line >= *from as i64)
+            }
+            Self::Symbol(name) => has_keyword(payload, "symbol", name),
+        }
+    }
+
+    /// How many of the seed's lines a document spans, to pick the closest match for a range.
+    pub(crate) fn overlap(&self, document: &Document) -> usize {
+        match (self, document) {
+            (Self::Range { from, to, .. }, Document::Code(code)) => {
+                let start = code.line_from.max(*from);
+                let end = code.line_to.min(*to);
+                (end + 1).saturating_sub(start)
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Lines `from` to `to` of `file` in the checkout at `root`, to embed a range that isn't indexed.
+/// `file` has to stay inside `root`.
+pub fn read_range(root: &Path, file: &str, from: usize, to: usize) -> Result<String> {
+    let relative = Path::new(file);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("{file} is not a path inside the repository");
+    }
+
+    let path = root.join(relative);
+    let contents =
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    let lines: Vec<&str> = contents
+        .lines()
+        .skip(from - 1)
+        .take(to + 1 - from)
+        .collect();
+    if lines.is_empty() {
+        bail!("{} has fewer than {from} lines", path.display());
+    }
+
+    Ok(lines.join("\n"))
+}
+
+pub(crate) fn point_id(id: &str) -> PointId {
+    match id.parse::<u64>() {
+        Ok(num) => num.into(),
+        Err(_) => id.to_string().into(),
+    }
+}
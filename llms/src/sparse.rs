@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::SparseConfig;
+
+/// The name of the sparse vector stored next to the dense ones.
+pub const LEXICAL_VECTOR: &str = "lexical";
+
+/// A sparse vector as `(term index, weight)` pairs.
+pub type SparseVector = Vec<(u32, f32)>;
+
+/// Tokens too common in Rust code or English prose to tell documents apart.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "fn", "if", "impl", "in", "is",
+    "it", "let", "mut", "of", "on", "or", "pub", "self", "that", "the", "this", "to", "use", "was",
+    "with",
+];
+
+/// BM25 term weights over identifier-aware tokens, for exact matches on names like
+/// `COLLECTION_NAME` that dense vectors blur.
+///
+/// Documents get BM25's saturated term frequencies and queries a weight of one per term. The
+/// frequencies depend on the average document length of the collection, so documents are encoded
+/// as [`TermCounts`] that the stores weigh when storing them. Before searching, the stores weigh
+/// each query term by its inverse document frequency with [`with_idf`], so the dot product Qdrant
+/// computes is the BM25 score. Terms are hashed into the index space, so no vocabulary has to be
+/// kept.
+pub struct SparseEncoder {
+    k1: f32,
+    b: f32,
+}
+
+/// How often each term occurs in a document, before it is weighed with [`TermCounts::weigh`].
+#[derive(Debug, Clone)]
+pub struct TermCounts {
+    counts: Vec<(u32, f32)>,
+    tokens: u64,
+    k1: f32,
+    b: f32,
+}
+
+/// How many documents of a collection got lexical vectors and how many tokens they had in all,
+/// giving the average document length BM25 normalises term frequencies with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DocumentLengths {
+    pub documents: u64,
+    pub tokens: u64,
+}
+
+impl SparseEncoder {
+    pub fn new(config: &SparseConfig) -> Self {
+        Self {
+            k1: config.k1,
+            b: config.b,
+        }
+    }
+
+    pub fn encode_passage(&self, text: &str) -> TermCounts {
+        let tokens = tokenize(text);
+
+        let mut counts: HashMap<u32, f32> = HashMap::new();
+        for token in &tokens {
+            *counts.entry(term_index(token)).or_default() += 1.0;
+        }
+
+        TermCounts {
+            counts: counts.into_iter().collect(),
+            tokens: tokens.len() as u64,
+            k1: self.k1,
+            b: self.b,
+        }
+    }
+
+    pub fn encode_query(&self, text: &str) -> SparseVector {
+        let mut indices: Vec<u32> = tokenize(text)
+            .iter()
+            .map(|token| term_index(token))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        indices.into_iter().map(|index| (index, 1.0)).collect()
+    }
+}
+
+impl TermCounts {
+    /// How many tokens the document has.
+    pub fn tokens(&self) -> u64 {
+        self.tokens
+    }
+
+    /// BM25's saturated term frequencies, in a collection whose documents average `avg_len`
+    /// tokens.
+    pub fn weigh(&self, avg_len: f32) -> SparseVector {
+        let norm = self.k1 * (1.0 - self.b + self.b * self.tokens as f32 / avg_len);
+        self.counts
+            .iter()
+            .map(|&(index, tf)| (index, tf * (self.k1 + 1.0) / (tf + norm)))
+            .collect()
+    }
+}
+
+impl DocumentLengths {
+    /// Weighs the terms of documents to be added to the collection, counting their lengths in
+    /// first so that the first documents of a collection are weighed against each other.
+    pub fn weigh<'a>(
+        &mut self,
+        documents: impl IntoIterator<Item = Option<&'a TermCounts>>,
+    ) -> Vec<Option<SparseVector>> {
+        let documents: Vec<Option<&TermCounts>> = documents.into_iter().collect();
+        for terms in documents.iter().flatten() {
+            self.documents += 1;
+            self.tokens += terms.tokens;
+        }
+
+        let avg_len = self.average();
+        documents
+            .into_iter()
+            .map(|terms| terms.map(|terms| terms.weigh(avg_len)))
+            .collect()
+    }
+
+    /// The average document length in tokens, at least one so that it can be divided by.
+    pub fn average(&self) -> f32 {
+        match self.documents {
+            0 => 1.0,
+            documents => (self.tokens as f32 / documents as f32).max(1.0),
+        }
+    }
+}
+
+/// Weighs the terms of a query by BM25's inverse document frequency, given how many of
+/// `documents` contain each of them (`frequencies`, in the order of `query`), so that rare terms
+/// count for more than common ones.
+pub fn with_idf(query: &SparseVector, documents: u64, frequencies: &[u64]) -> SparseVector {
+    query
+        .iter()
+        .zip(frequencies)
+        .map(|(&(index, weight), &frequency)| {
+            let (n, df) = (documents as f32, frequency.min(documents) as f32);
+            (index, weight * (1.0 + (n - df + 0.5) / (df + 0.5)).ln())
+        })
+        .collect()
+}
+
+/// Lowercased words and identifiers, with identifiers also split into their parts:
+/// `hub_load_safetensors` gives `hub_load_safetensors`, `hub`, `load` and `safetensors`, and
+/// `TokenOutputStream` gives `tokenoutputstream`, `token`, `output` and `stream`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let word = word.trim_matches('_');
+        if word.is_empty() {
+            continue;
+        }
+
+        let parts = identifier_parts(word);
+        if parts.len() > 1 {
+            tokens.push(word.to_lowercase());
+        }
+        tokens.extend(parts);
+    }
+
+    tokens.retain(|token| token.chars().count() > 1 && !STOP_WORDS.contains(&token.as_str()));
+    tokens
+}
+
+/// Splits on underscores and camelCase boundaries, keeping acronyms together (`HTTPServer` gives
+/// `http` and `server`).
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+
+    for segment in word.split('_').filter(|segment| !segment.is_empty()) {
+        let chars: Vec<char> = segment.chars().collect();
+        let mut current = String::new();
+
+        for (i, &c) in chars.iter().enumerate() {
+            let boundary = i > 0
+                && c.is_uppercase()
+                && (chars[i - 1].is_lowercase()
+                    || chars[i - 1].is_numeric()
+                    || chars.get(i + 1).is_some_and(|next| next.is_lowercase()));
+
+            if boundary && !current.is_empty() {
+                parts.push(current.to_lowercase());
+                current.clear();
+            }
+            current.push(c);
+        }
+        if !current.is_empty() {
+            parts.push(current.to_lowercase());
+        }
+    }
+
+    parts
+}
+
+/// FNV-1a, which unlike the std hasher is stable across builds, as indices are stored.
+fn term_index(token: &str) -> u32 {
+    token.bytes().fold(0x811c9dc5, |hash: u32, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_identifiers_into_their_parts() {
+        assert_eq!(
+            tokenize("parseHttpRequest"),
+            ["parsehttprequest", "parse", "http", "request"]
+        );
+        assert_eq!(tokenize("snake_case"), ["snake_case", "snake", "case"]);
+        assert_eq!(tokenize("HTTPServer"), ["httpserver", "http", "server"]);
+    }
+
+    #[test]
+    fn drops_stop_words_and_single_characters() {
+        assert_eq!(tokenize("fn x(self) is the Parser"), ["parser"]);
+    }
+
+    #[test]
+    fn rare_terms_weigh_more() {
+        let query = vec![(1, 1.0), (2, 1.0)];
+        let weighted = with_idf(&query, 100, &[1, 100]);
+
+        assert!(weighted[0].1 > weighted[1].1);
+        assert!(weighted[1].1 > 0.0);
+    }
+
+    #[test]
+    fn longer_documents_weigh_terms_less() {
+        let encoder = SparseEncoder::new(&SparseConfig::default());
+        let short = encoder.encode_passage("parser");
+        let long = encoder.encode_passage("parser tokens weights vectors");
+
+        let mut lengths = DocumentLengths::default();
+        let weighted = lengths.weigh([Some(&short), Some(&long), None]);
+        assert_eq!(lengths.documents, 2);
+        assert_eq!(lengths.tokens, 5);
+        assert!(weighted[2].is_none());
+
+        let weight = |vector: &Option<SparseVector>| {
+            let parser = term_index("parser");
+            vector
+                .as_ref()
+                .unwrap()
+                .iter()
+                .find(|(index, _)| *index == parser)
+                .unwrap()
+                .1
+        };
+        assert!(weight(&weighted[0]) > weight(&weighted[1]));
+    }
+}
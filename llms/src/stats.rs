@@ -0,0 +1,188 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use parser::DocumentKind;
+use qdrant_client::qdrant::value::Kind;
+use qdrant_client::qdrant::Value;
+use serde::Serialize;
+
+use crate::schema::Schema;
+use crate::store::{VectorStore, NAMESPACE_KEY};
+
+/// Payload keys every point is indexed with.
+const FIELDS: &[&str] = &[
+    "kind",
+    "files",
+    "dirs",
+    "parent",
+    "template_version",
+    "indexed_at",
+];
+
+/// Payload keys every code point is indexed with.
+const CODE_FIELDS: &[&str] = &["code_type", "symbol", "visibility", "test"];
+
+/// What is in a collection.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Stats {
+    pub collection: String,
+    /// The models and templates the collection was created with.
+    pub schema: Option<Schema>,
+    pub points: u64,
+    pub kinds: BTreeMap<String, u64>,
+    /// By `owner/name`, with documents indexed without a repository under `""`.
+    pub repos: BTreeMap<String, RepoStats>,
+    pub crates: BTreeMap<String, u64>,
+    pub code_types: BTreeMap<String, u64>,
+    /// How many points were rendered with each version of the templates.
+    pub template_versions: BTreeMap<i64, u64>,
+    /// How many points lack each payload field they should have, usually because they were
+    /// indexed by an older version.
+    pub missing_fields: BTreeMap<String, u64>,
+    /// The IDs of points holding the same document, e.g. after embedding a directory twice.
+    pub duplicates: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepoStats {
+    pub points: u64,
+    /// The commit its code was last synced at.
+    pub commit: Option<String>,
+    /// When a document of the repository was last indexed, in seconds since the Unix epoch.
+    pub last_indexed: Option<i64>,
+}
+
+impl RepoStats {
+    /// How long ago the repository was last indexed, as in `3h ago`.
+    pub fn indexed_ago(&self) -> Option<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let seconds = (now - self.last_indexed?).max(0);
+
+        let ago = match seconds {
+            0..=59 => format!("{seconds}s"),
+            60..=3599 => format!("{}m", seconds / 60),
+            3600..=86399 => format!("{}h", seconds / 3600),
+            _ => format!("{}d", seconds / 86400),
+        };
+        Some(format!("{ago} ago"))
+    }
+}
+
+impl Stats {
+    /// Goes through every point of the store's namespace.
+    pub async fn collect(store: &impl VectorStore) -> Result<Self> {
+        let mut stats = Self {
+            collection: store.collection().to_string(),
+            schema: store.indexed_schema().await?,
+            ..Default::default()
+        };
+
+        let mut documents: HashMap<u64, Vec<String>> = HashMap::new();
+        store
+            .for_each_point(|id, payload| {
+                stats.add(&payload);
+                documents
+                    .entry(document_hash(&payload))
+                    .or_default()
+                    .push(id);
+            })
+            .await?;
+
+        stats.duplicates = documents
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .collect();
+        stats.duplicates.sort();
+
+        for (name, repo) in &mut stats.repos {
+            repo.commit = store.indexed_commit(name).await?;
+        }
+
+        Ok(stats)
+    }
+
+    /// How many points hold a document that another point holds too.
+    pub fn duplicate_points(&self) -> usize {
+        self.duplicates.iter().map(|ids| ids.len() - 1).sum()
+    }
+
+    fn add(&mut self, payload: &HashMap<String, Value>) {
+        let text = |key| payload.get(key).and_then(Value::as_str).cloned();
+
+        self.points += 1;
+        let kind = text("kind").unwrap_or_else(|| DocumentKind::Code.to_string());
+        *self.kinds.entry(kind.clone()).or_default() += 1;
+
+        let repo = self
+            .repos
+            .entry(text("repo").unwrap_or_default())
+            .or_default();
+        repo.points += 1;
+        if let Some(indexed_at) = payload.get("indexed_at").and_then(Value::as_integer) {
+            repo.last_indexed = repo.last_indexed.max(Some(indexed_at));
+        }
+
+        if let Some(crate_name) = text("crate") {
+            *self.crates.entry(crate_name).or_default() += 1;
+        }
+        if let Some(code_type) = text("code_type") {
+            *self.code_types.entry(code_type).or_default() += 1;
+        }
+        if let Some(version) = payload.get("template_version").and_then(Value::as_integer) {
+            *self.template_versions.entry(version).or_default() += 1;
+        }
+
+        let code_fields = match kind == DocumentKind::Code.to_string() {
+            true => CODE_FIELDS,
+            false => &[],
+        };
+        for field in FIELDS.iter().chain(code_fields) {
+            if !payload.contains_key(*field) {
+                *self.missing_fields.entry(field.to_string()).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// Identifies the document a point holds, within its repository and namespace.
+fn document_hash(payload: &HashMap<String, Value>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for key in ["repo", NAMESPACE_KEY, "data"] {
+        key.hash(&mut hasher);
+        if let Some(value) = payload.get(key) {
+            hash_value(value, &mut hasher);
+        }
+    }
+
+    hasher.finish()
+}
+
+/// Hashes struct fields in order of their keys, as they are kept in a `HashMap`.
+fn hash_value(value: &Value, hasher: &mut impl Hasher) {
+    match &value.kind {
+        Some(Kind::StructValue(value)) => {
+            0u8.hash(hasher);
+            let mut fields: Vec<_> = value.fields.iter().collect();
+            fields.sort_by_key(|(key, _)| *key);
+            for (key, value) in fields {
+                key.hash(hasher);
+                hash_value(value, hasher);
+            }
+        }
+        Some(Kind::ListValue(list)) => {
+            1u8.hash(hasher);
+            list.values.len().hash(hasher);
+            for value in &list.values {
+                hash_value(value, hasher);
+            }
+        }
+        Some(Kind::StringValue(text)) => (2u8, text).hash(hasher),
+        Some(Kind::IntegerValue(int)) => (3u8, int).hash(hasher),
+        Some(Kind::DoubleValue(double)) => (4u8, double.to_bits()).hash(hasher),
+        Some(Kind::BoolValue(bool)) => (5u8, bool).hash(hasher),
+        Some(Kind::NullValue(_)) | None => 6u8.hash(hasher),
+    }
+}
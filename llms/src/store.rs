@@ -0,0 +1,696 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use parser::{Document, DocumentKind};
+use qdrant_client::qdrant::Value;
+use serde::Serialize;
+
+use crate::config::{Config, Fusion, GroupBy, SearchConfig, StoreBackend};
+use crate::export::ExportedPoint;
+use crate::filter::{self, SearchFilter, Selection};
+use crate::local::LocalStore;
+use crate::qdrant::Qdrant;
+use crate::schema::Schema;
+use crate::similar::Seed;
+use crate::{CombinedEmbedding, Embedder, Embedding};
+
+/// The payload key namespaces sharing a collection are told apart by.
+pub(crate) const NAMESPACE_KEY: &str = "namespace";
+
+/// The payload key listing the lexical terms of a point, so that Qdrant can count the documents
+/// containing a term.
+pub(crate) const TERMS_KEY: &str = "terms";
+
+/// Payload keys that aren't derived from the document, and so are carried over by migrations.
+pub(crate) const PRESERVED_KEYS: &[&str] = &[NAMESPACE_KEY];
+
+/// What the commit `repo` was last synced at is recorded under. It is kept apart from the code
+/// points, as a repository may have none.
+pub(crate) fn commit_key(tenant: Option<&str>, repo: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("commit:{tenant}:{repo}"),
+        None => format!("commit:{repo}"),
+    }
+}
+
+/// How many points are re-embedded at a time when migrating.
+pub(crate) const MIGRATION_PAGE: u32 = 64;
+
+/// How many points a seed may match, e.g. a symbol defined in several repositories.
+pub(crate) const SEED_LIMIT: u32 = 16;
+
+/// Somewhere embedded documents are stored and searched: a Qdrant instance, or a local file.
+#[allow(async_fn_in_trait)]
+pub trait VectorStore {
+    /// The same store, scoped to `namespace` instead.
+    fn with_namespace(&self, namespace: &str) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// The name of the collection documents are stored in.
+    fn collection(&self) -> &str;
+
+    /// Creates the collection if it doesn't exist yet, otherwise checks that it was indexed with
+    /// the embedder's models.
+    async fn ensure_collection(&self, embedder: &Embedder) -> Result<CollectionStatus>;
+
+    /// The schema the collection was created with, if it exists and has one.
+    async fn indexed_schema(&self) -> Result<Option<Schema>>;
+
+    /// Calls `visit` with the ID and payload of every document in the namespace.
+    async fn for_each_point(&self, visit: impl FnMut(String, HashMap<String, Value>))
+        -> Result<()>;
+
+    /// Calls `visit` with every point of the namespace, vectors included, stopping at the first
+    /// error it returns.
+    async fn export_points(&self, visit: impl FnMut(ExportedPoint) -> Result<()>) -> Result<()>;
+
+    /// Stores exported points in the namespace under their own IDs, replacing any points that
+    /// already have them.
+    async fn import_points(&self, points: Vec<ExportedPoint>) -> Result<()>;
+
+    /// Re-embeds every document with the embedder's models, reporting how many are done.
+    async fn migrate(
+        &self,
+        embedder: &Embedder,
+        keep_old: bool,
+        progress: impl Fn(usize),
+    ) -> Result<Migration>;
+
+    /// Stores documents, reporting those that couldn't be stored rather than failing.
+    async fn insert_docs(
+        &self,
+        docs: Vec<Embedding>,
+        progress: impl Fn(InsertProgress),
+    ) -> Result<InsertReport>;
+
+    /// The commit the code of `repo` was last fully synced at.
+    async fn indexed_commit(&self, repo: &str) -> Result<Option<String>>;
+
+    /// Records that the code of `repo` is synced at `commit`.
+    async fn set_commit(&self, repo: &str, commit: &str) -> Result<()>;
+
+    /// Deletes the code points of `repo`, only those from `files` if given. Deleting all of them
+    /// also forgets the commit they were synced at.
+    async fn delete_code(&self, repo: &str, files: Option<Vec<String>>) -> Result<()>;
+
+    /// Deletes the points in `selection`, returning how many there were. Selecting nothing is an
+    /// error rather than deleting everything.
+    async fn delete(&self, selection: &Selection) -> Result<u64>;
+
+    /// The files the code of `repo` was indexed from, or those of code indexed without a
+    /// repository if no `repo` is given.
+    async fn indexed_files(&self, repo: Option<&str>) -> Result<BTreeSet<String>>;
+
+    /// Deletes the code of indexed files that no longer exist under `root`, which is the checkout
+    /// of `repo` if given.
+    async fn prune(&self, root: &Path, repo: Option<&str>) -> Result<Pruned> {
+        let files: Vec<String> = self
+            .indexed_files(repo)
+            .await?
+            .into_iter()
+            .filter(|file| !root.join(file).exists())
+            .collect();
+        if files.is_empty() {
+            return Ok(Pruned::default());
+        }
+
+        let selection = Selection {
+            kinds: vec![DocumentKind::Code],
+            repo: repo.map(ToString::to_string),
+            without_repo: repo.is_none(),
+            files: files.clone(),
+            ..Default::default()
+        };
+        let points = self.delete(&selection).await?;
+
+        Ok(Pruned { files, points })
+    }
+
+    /// Searches each vector for `options.limit` points, then fuses the results into a single
+    /// ranking of at most that many.
+    async fn search(
+        &self,
+        embedding: CombinedEmbedding,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>>;
+
+    /// The indexed points `seed` refers to, closest match first, with their `vector`.
+    ///
+    /// Ranges and symbols are looked up in `repo` if given.
+    async fn lookup(&self, seed: &Seed, vector: &str, repo: Option<&str>)
+        -> Result<Vec<SeedPoint>>;
+
+    /// The nearest neighbours of `embedding` by `vector`, leaving out the points in `exclude`.
+    async fn similar(
+        &self,
+        vector: &str,
+        embedding: Vec<f32>,
+        exclude: &[String],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>>;
+}
+
+/// The store picked by the config.
+#[derive(Clone)]
+pub enum AnyStore {
+    Qdrant(Qdrant),
+    Local(LocalStore),
+}
+
+impl AnyStore {
+    /// Connects to Qdrant, or opens the local store under `store.dir` (`.duckyduck` by default),
+    /// scoped to the configured namespace.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let store = match config.store.backend {
+            StoreBackend::Qdrant => Self::Qdrant(Qdrant::from_config(&config.qdrant)?),
+            StoreBackend::Local => {
+                let dir = config
+                    .store
+                    .dir
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(".duckyduck"));
+                Self::Local(LocalStore::open(dir, &config.qdrant)?)
+            }
+        };
+
+        Ok(store)
+    }
+}
+
+impl VectorStore for AnyStore {
+    fn with_namespace(&self, namespace: &str) -> Result<Self> {
+        match self {
+            Self::Qdrant(store) => store.with_namespace(namespace).map(Self::Qdrant),
+            Self::Local(store) => store.with_namespace(namespace).map(Self::Local),
+        }
+    }
+
+    fn collection(&self) -> &str {
+        match self {
+            Self::Qdrant(store) => store.collection(),
+            Self::Local(store) => store.collection(),
+        }
+    }
+
+    async fn ensure_collection(&self, embedder: &Embedder) -> Result<CollectionStatus> {
+        match self {
+            Self::Qdrant(store) => store.ensure_collection(embedder).await,
+            Self::Local(store) => store.ensure_collection(embedder).await,
+        }
+    }
+
+    async fn indexed_schema(&self) -> Result<Option<Schema>> {
+        match self {
+            Self::Qdrant(store) => store.indexed_schema().await,
+            Self::Local(store) => store.indexed_schema().await,
+        }
+    }
+
+    async fn for_each_point(
+        &self,
+        visit: impl FnMut(String, HashMap<String, Value>),
+    ) -> Result<()> {
+        match self {
+            Self::Qdrant(store) => store.for_each_point(visit).await,
+            Self::Local(store) => store.for_each_point(visit).await,
+        }
+    }
+
+    async fn export_points(&self, visit: impl FnMut(ExportedPoint) -> Result<()>) -> Result<()> {
+        match self {
+            Self::Qdrant(store) => store.export_points(visit).await,
+            Self::Local(store) => store.export_points(visit).await,
+        }
+    }
+
+    async fn import_points(&self, points: Vec<ExportedPoint>) -> Result<()> {
+        match self {
+            Self::Qdrant(store) => store.import_points(points).await,
+            Self::Local(store) => store.import_points(points).await,
+        }
+    }
+
+    async fn migrate(
+        &self,
+        embedder: &Embedder,
+        keep_old: bool,
+        progress: impl Fn(usize),
+    ) -> Result<Migration> {
+        match self {
+            Self::Qdrant(store) => store.migrate(embedder, keep_old, progress).await,
+            Self::Local(store) => store.migrate(embedder, keep_old, progress).await,
+        }
+    }
+
+    async fn insert_docs(
+        &self,
+        docs: Vec<Embedding>,
+        progress: impl Fn(InsertProgress),
+    ) -> Result<InsertReport> {
+        match self {
+            Self::Qdrant(store) => store.insert_docs(docs, progress).await,
+            Self::Local(store) => store.insert_docs(docs, progress).await,
+        }
+    }
+
+    async fn indexed_commit(&self, repo: &str) -> Result<Option<String>> {
+        match self {
+            Self::Qdrant(store) => store.indexed_commit(repo).await,
+            Self::Local(store) => store.indexed_commit(repo).await,
+        }
+    }
+
+    async fn set_commit(&self, repo: &str, commit: &str) -> Result<()> {
+        match self {
+            Self::Qdrant(store) => store.set_commit(repo, commit).await,
+            Self::Local(store) => store.set_commit(repo, commit).await,
+        }
+    }
+
+    async fn delete_code(&self, repo: &str, files: Option<Vec<String>>) -> Result<()> {
+        match self {
+            Self::Qdrant(store) => store.delete_code(repo, files).await,
+            Self::Local(store) => store.delete_code(repo, files).await,
+        }
+    }
+
+    async fn delete(&self, selection: &Selection) -> Result<u64> {
+        match self {
+            Self::Qdrant(store) => store.delete(selection).await,
+            Self::Local(store) => store.delete(selection).await,
+        }
+    }
+
+    async fn indexed_files(&self, repo: Option<&str>) -> Result<BTreeSet<String>> {
+        match self {
+            Self::Qdrant(store) => store.indexed_files(repo).await,
+            Self::Local(store) => store.indexed_files(repo).await,
+        }
+    }
+
+    async fn search(
+        &self,
+        embedding: CombinedEmbedding,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        match self {
+            Self::Qdrant(store) => store.search(embedding, options).await,
+            Self::Local(store) => store.search(embedding, options).await,
+        }
+    }
+
+    async fn lookup(
+        &self,
+        seed: &Seed,
+        vector: &str,
+        repo: Option<&str>,
+    ) -> Result<Vec<SeedPoint>> {
+        match self {
+            Self::Qdrant(store) => store.lookup(seed, vector, repo).await,
+            Self::Local(store) => store.lookup(seed, vector, repo).await,
+        }
+    }
+
+    async fn similar(
+        &self,
+        vector: &str,
+        embedding: Vec<f32>,
+        exclude: &[String],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        match self {
+            Self::Qdrant(store) => store.similar(vector, embedding, exclude, options).await,
+            Self::Local(store) => store.similar(vector, embedding, exclude, options).await,
+        }
+    }
+}
+
+/// The state of the collection, as found by [`VectorStore::ensure_collection`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectionStatus {
+    Created,
+    Ready,
+    /// The points can be searched, but should be migrated for the given reasons.
+    Outdated(Vec<String>),
+}
+
+/// How far [`VectorStore::insert_docs`] has got, in points.
+#[derive(Debug, Clone, Copy)]
+pub struct InsertProgress {
+    pub inserted: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// A batch of points that couldn't be stored, even after retrying.
+#[derive(Debug, Clone)]
+pub struct BatchFailure {
+    /// The index of the batch, in the order the documents were given.
+    pub batch: usize,
+    pub points: usize,
+    pub error: String,
+}
+
+/// The outcome of [`VectorStore::insert_docs`].
+#[derive(Debug, Clone, Default)]
+pub struct InsertReport {
+    pub inserted: usize,
+    pub failures: Vec<BatchFailure>,
+}
+
+impl InsertReport {
+    /// How many points weren't stored.
+    pub fn failed(&self) -> usize {
+        self.failures.iter().map(|failure| failure.points).sum()
+    }
+}
+
+/// The outcome of [`VectorStore::migrate`].
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub from: String,
+    pub to: String,
+    pub documents: usize,
+}
+
+/// The outcome of [`VectorStore::prune`].
+#[derive(Debug, Clone, Default)]
+pub struct Pruned {
+    /// The indexed files that no longer exist.
+    pub files: Vec<String>,
+    /// How many points were deleted.
+    pub points: u64,
+}
+
+/// Which vectors `search` matches the prompt against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// The dense vectors of every embedding model.
+    Dense,
+    /// Only the lexical sparse vector.
+    Sparse,
+    /// Both.
+    #[default]
+    Hybrid,
+}
+
+/// Fails if `selection` would delete every point.
+pub(crate) fn check_selection(selection: &Selection) -> Result<()> {
+    if selection.is_empty() {
+        return Err(anyhow!("no points were selected to delete"));
+    }
+
+    Ok(())
+}
+
+/// Namespaces end up in collection names and payloads, so they are kept to a safe set of
+/// characters.
+pub(crate) fn check_namespace(namespace: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if namespace.is_empty() || !namespace.chars().all(valid) {
+        return Err(anyhow!(
+            "namespace `{namespace}` may only contain letters, digits, `_` and `-`"
+        ));
+    }
+
+    Ok(())
+}
+
+/// A point found by one of the vectors of a search, before fusion.
+pub(crate) struct Hit {
+    pub id: String,
+    pub score: f32,
+    pub payload: HashMap<String, Value>,
+}
+
+/// An indexed point a "find similar" search starts from.
+pub struct SeedPoint {
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub document: Document,
+}
+
+/// A document found by a search, with how well it matched.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    /// The ID of its point in the collection.
+    pub id: String,
+    /// The score given by each vector that found it, by vector name.
+    pub scores: HashMap<String, f32>,
+    /// The fused score results are ordered by.
+    pub score: f32,
+    /// The cross-encoder's score, if the results were reranked.
+    pub rerank_score: Option<f32>,
+    /// The group it was put in, if results were grouped.
+    pub group: Option<String>,
+    pub document: Document,
+}
+
+/// What `search` matches against and how the results of each vector are combined.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub mode: SearchMode,
+    /// How many results are returned.
+    pub limit: u64,
+    pub fusion: Fusion,
+    pub rrf_k: f32,
+    /// How much each vector counts towards the fused score. Missing vectors count 1.
+    pub weights: HashMap<String, f32>,
+    pub filter: SearchFilter,
+    /// Whether results found with quantized vectors are rescored with the original vectors.
+    pub rescore: Option<bool>,
+    pub oversampling: Option<f64>,
+    /// How many results (or groups) to skip, for paging through them.
+    pub offset: u64,
+    /// Groups results, making `limit` and `offset` count groups instead.
+    pub group_by: Option<GroupBy>,
+    /// How many results each group keeps.
+    pub group_size: u64,
+}
+
+impl SearchOptions {
+    pub fn from_config(config: &SearchConfig) -> Self {
+        Self {
+            mode: SearchMode::default(),
+            limit: config.limit,
+            fusion: config.fusion,
+            rrf_k: config.rrf_k,
+            weights: config.weights.clone().into_iter().collect(),
+            filter: SearchFilter::default(),
+            rescore: config.rescore,
+            oversampling: config.oversampling,
+            offset: 0,
+            group_by: config.group_by,
+            group_size: config.group_size,
+        }
+    }
+
+    /// How many results each vector has to find to fill the requested page.
+    pub(crate) fn candidates(&self) -> u64 {
+        let per_group = match self.group_by {
+            Some(_) => self.group_size.max(1),
+            None => 1,
+        };
+
+        (self.offset + self.limit) * per_group
+    }
+
+    /// The requested page of ranked results. When grouping, groups are ordered by their best
+    /// result and keep up to `group_size` results each.
+    pub(crate) fn page(&self, results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let offset = self.offset as usize;
+        let limit = self.limit as usize;
+        let Some(group_by) = self.group_by else {
+            return results.into_iter().skip(offset).take(limit).collect();
+        };
+
+        let mut groups: Vec<Vec<SearchResult>> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for mut result in results {
+            let key =
+                filter::group_key(&result.document, group_by).unwrap_or_else(|| result.id.clone());
+            let position = *positions.entry(key.clone()).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+
+            let group = &mut groups[position];
+            if group.len() < self.group_size.max(1) as usize {
+                result.group = Some(key);
+                group.push(result);
+            }
+        }
+
+        groups
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .flatten()
+            .collect()
+    }
+
+    fn weight(&self, vector: &str) -> f32 {
+        self.weights.get(vector).copied().unwrap_or(1.0)
+    }
+
+    /// Merges the results of each vector, counting points found by several vectors once.
+    ///
+    /// With reciprocal rank fusion, points score the sum of `weight / (k + rank)` over the lists
+    /// they appear in, which needs no comparison between dense and sparse scores. With weighted
+    /// fusion, each list's scores are divided by its best one so that cosine similarities and
+    /// BM25 scores are on the same scale before being weighted and summed.
+    pub(crate) fn fuse(&self, lists: Vec<(String, Vec<Hit>)>) -> Result<Vec<SearchResult>> {
+        let mut fused: Vec<SearchResult> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+
+        for (vector, list) in lists {
+            let weight = self.weight(&vector);
+            let best = list.first().map_or(1.0, |point| point.score);
+
+            for (rank, point) in list.into_iter().enumerate() {
+                let contribution = match self.fusion {
+                    Fusion::Rrf => weight / (self.rrf_k + rank as f32 + 1.0),
+                    Fusion::Weighted if best > 0.0 => weight * point.score / best,
+                    Fusion::Weighted => weight * point.score,
+                };
+
+                let id = point.id;
+                let result = match positions.entry(id.clone()) {
+                    Entry::Occupied(entry) => &mut fused[*entry.get()],
+                    Entry::Vacant(entry) => {
+                        entry.insert(fused.len());
+                        fused.push(SearchResult {
+                            id,
+                            scores: HashMap::new(),
+                            score: 0.0,
+                            rerank_score: None,
+                            group: None,
+                            document: into_document(point.payload)?,
+                        });
+                        fused.last_mut().unwrap()
+                    }
+                };
+
+                result.scores.insert(vector.clone(), point.score);
+                result.score += contribution;
+            }
+        }
+
+        fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(fused)
+    }
+}
+
+/// The payload a document is stored with: its filterable fields, the document itself, the
+/// version of the templates it was rendered with and when it was indexed.
+pub(crate) fn point_payload(
+    embedding: &Embedding,
+    tenant: Option<&str>,
+) -> Result<HashMap<String, Value>> {
+    let value = match &embedding.document {
+        Document::Code(code) => serde_json::to_value(code)?,
+        Document::Discussion(discussion) => serde_json::to_value(discussion)?,
+    };
+
+    let mut payload = filter::payload(&embedding.document);
+    payload.insert("data".to_string(), value.into());
+    if let Some(tenant) = tenant {
+        payload.insert(NAMESPACE_KEY.to_string(), tenant.to_string().into());
+    }
+    payload.insert(
+        "template_version".to_string(),
+        (embedding.template_version as i64).into(),
+    );
+    let indexed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    payload.insert("indexed_at".to_string(), (indexed_at as i64).into());
+
+    Ok(payload)
+}
+
+/// Reads a point's payload back into the document it was created from.
+/// Points without a `kind` predate discussions being indexed and are always code.
+pub(crate) fn into_document(mut payload: HashMap<String, Value>) -> Result<Document> {
+    let data: serde_json::Value = payload
+        .remove("data")
+        .ok_or_else(|| anyhow!("point has no data in its payload"))?
+        .into();
+
+    let kind = match payload.remove("kind") {
+        Some(kind) => serde_json::from_value(kind.into())?,
+        None => DocumentKind::Code,
+    };
+
+    let document = match kind {
+        DocumentKind::Code => Document::Code(serde_json::from_value(data)?),
+        _ => Document::Discussion(serde_json::from_value(data)?),
+    };
+
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(id: &str, score: f32) -> Hit {
+        let data = serde_json::json!({
+            "name": id,
+            "signature": format!("fn {id}()"),
+            "code_type": "Function",
+            "docstring": null,
+            "line": 1,
+            "line_from": 1,
+            "line_to": 1,
+            "context": null,
+        });
+
+        Hit {
+            id: id.to_string(),
+            score,
+            payload: HashMap::from([("data".to_string(), data.into())]),
+        }
+    }
+
+    fn lists() -> Vec<(String, Vec<Hit>)> {
+        vec![
+            ("code".to_string(), vec![hit("a", 0.9), hit("b", 0.6)]),
+            ("lexical".to_string(), vec![hit("b", 8.0), hit("c", 2.0)]),
+        ]
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.id.as_str()).collect()
+    }
+
+    #[test]
+    fn rrf_sums_reciprocal_ranks() {
+        let options = SearchOptions::from_config(&SearchConfig::default());
+        let results = options.fuse(lists()).unwrap();
+
+        assert_eq!(ids(&results), ["b", "a", "c"]);
+        assert_eq!(results[0].score, 1.0 / 62.0 + 1.0 / 61.0);
+        assert_eq!(
+            results[0].scores,
+            HashMap::from([("code".to_string(), 0.6), ("lexical".to_string(), 8.0),])
+        );
+        assert_eq!(results[1].score, 1.0 / 61.0);
+        assert_eq!(results[2].score, 1.0 / 62.0);
+    }
+
+    #[test]
+    fn weighted_scales_each_list_by_its_best_score() {
+        let mut options = SearchOptions::from_config(&SearchConfig::default());
+        options.fusion = Fusion::Weighted;
+        options.weights.insert("lexical".to_string(), 2.0);
+        let results = options.fuse(lists()).unwrap();
+
+        assert_eq!(ids(&results), ["b", "a", "c"]);
+        assert_eq!(results[0].score, 0.6 / 0.9 + 2.0);
+        assert_eq!(results[1].score, 1.0);
+        assert_eq!(results[2].score, 0.5);
+    }
+}
@@ -0,0 +1,140 @@
+//! Restores snapshots on a local Qdrant, as started by `make docker-qdrant`. Run with
+//! `make test-qdrant`; `QDRANT_URL` points them at another instance.
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use llms::config::QdrantConfig;
+use llms::qdrant::Qdrant;
+use qdrant_client::client::{Payload, QdrantClient};
+use qdrant_client::qdrant::vectors_config::Config;
+use qdrant_client::qdrant::{
+    CountPoints, CreateCollection, Distance, PointStruct, VectorParams, VectorsConfig,
+};
+
+const POINTS: u64 = 10;
+
+fn config(collection: &str) -> QdrantConfig {
+    QdrantConfig {
+        url: std::env::var("QDRANT_URL").unwrap_or_else(|_| "http://localhost:6334".to_string()),
+        collection: collection.to_string(),
+        ..Default::default()
+    }
+}
+
+fn unique_name(prefix: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("snapshot_test_{prefix}_{nanos}")
+}
+
+async fn seed(client: &QdrantClient, collection: &str) -> Result<()> {
+    client
+        .create_collection(&CreateCollection {
+            collection_name: collection.to_string(),
+            vectors_config: Some(VectorsConfig {
+                config: Some(Config::Params(VectorParams {
+                    size: 4,
+                    distance: Distance::Cosine.into(),
+                    ..Default::default()
+                })),
+            }),
+            ..Default::default()
+        })
+        .await?;
+
+    let points = (0..POINTS)
+        .map(|id| PointStruct::new(id, vec![id as f32, 1.0, 0.0, 0.0], Payload::new()))
+        .collect();
+    client
+        .upsert_points_blocking(collection, None, points, None)
+        .await?;
+
+    Ok(())
+}
+
+async fn count(client: &QdrantClient, collection: &str) -> Result<u64> {
+    let res = client
+        .count(&CountPoints {
+            collection_name: collection.to_string(),
+            exact: Some(true),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(res.result.map_or(0, |result| result.count))
+}
+
+async fn aliased_collection(client: &QdrantClient, alias: &str) -> Result<Option<String>> {
+    let aliases = client.list_aliases().await?.aliases;
+
+    Ok(aliases
+        .into_iter()
+        .find(|a| a.alias_name == alias)
+        .map(|a| a.collection_name))
+}
+
+#[tokio::test]
+#[ignore = "needs a local Qdrant"]
+async fn restores_over_an_aliased_collection() -> Result<()> {
+    let config = config(&unique_name("aliased"));
+    let client = QdrantClient::from_url(&config.url).build()?;
+    let first = format!("{}_1", config.collection);
+    seed(&client, &first).await?;
+    client.create_alias(&first, &config.collection).await?;
+
+    let qdrant = Qdrant::from_config(&config)?;
+    let snapshot = qdrant.create_snapshot().await?;
+    assert!(qdrant
+        .list_snapshots()
+        .await?
+        .iter()
+        .any(|listed| listed.name == snapshot.name));
+
+    let path = std::env::temp_dir().join(format!("{}.snapshot", config.collection));
+    let size = qdrant.download_snapshot(&snapshot.name, &path).await?;
+    assert_eq!(size, fs::metadata(&path)?.len());
+
+    let restore = qdrant.restore_snapshot(&path, false).await?;
+    assert_eq!(restore.from.as_deref(), Some(first.as_str()));
+    assert_eq!(
+        aliased_collection(&client, &config.collection).await?,
+        Some(restore.to.clone())
+    );
+    assert!(!client.collection_exists(&first).await?);
+    assert_eq!(count(&client, &config.collection).await?, POINTS);
+
+    client.delete_collection(&restore.to).await?;
+    fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs a local Qdrant"]
+async fn restores_over_a_collection_without_alias() -> Result<()> {
+    let config = config(&unique_name("legacy"));
+    let client = QdrantClient::from_url(&config.url).build()?;
+    seed(&client, &config.collection).await?;
+
+    let qdrant = Qdrant::from_config(&config)?;
+    let snapshot = qdrant.create_snapshot().await?;
+    let path = std::env::temp_dir().join(format!("{}.snapshot", config.collection));
+    qdrant.download_snapshot(&snapshot.name, &path).await?;
+
+    let restore = qdrant.restore_snapshot(&path, true).await?;
+    assert_eq!(restore.from.as_deref(), Some(config.collection.as_str()));
+    assert_eq!(
+        aliased_collection(&client, &config.collection).await?,
+        Some(restore.to.clone())
+    );
+    assert_eq!(count(&client, &config.collection).await?, POINTS);
+
+    client.delete_collection(&restore.to).await?;
+    fs::remove_file(&path)?;
+
+    Ok(())
+}
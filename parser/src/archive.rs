@@ -0,0 +1,141 @@
+use async_compression::tokio::bufread::GzipDecoder;
+use bytes::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt};
+use std::io;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use tokio_tar::Archive;
+use tokio_util::io::StreamReader;
+
+use crate::source::Error;
+
+/// How many bytes of an archive have been downloaded so far, out of `total` when the size is known.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+/// Limits and progress reporting for fetching a repository.
+#[derive(Clone, Default)]
+pub struct FetchOptions {
+    /// Abort once more than this many (compressed) bytes have been downloaded.
+    pub max_download_bytes: Option<u64>,
+    /// Abort once the unpacked files add up to more than this many bytes.
+    pub max_unpacked_bytes: Option<u64>,
+    pub progress: Option<Arc<dyn Fn(Progress) + Send + Sync>>,
+}
+
+impl FetchOptions {
+    pub fn with_progress(mut self, progress: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+/// Streams a gzipped tarball straight from `body` into `dest`, without buffering it in memory.
+///
+/// Entries that would be written outside of `dest` (absolute paths, `..` components, or links
+/// pointing out of the archive) are rejected and abort the unpack.
+pub(crate) async fn unpack_tarball<S>(
+    body: S,
+    total: Option<u64>,
+    dest: &Path,
+    options: &FetchOptions,
+) -> Result<(), Error>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    if let (Some(total), Some(max)) = (total, options.max_download_bytes) {
+        if total > max {
+            return Err(Error::Archive(format!(
+                "archive is {total} bytes, over the {max} byte limit"
+            )));
+        }
+    }
+
+    let mut downloaded = 0;
+    let body = body.map(|chunk| {
+        let chunk = chunk?;
+        downloaded += chunk.len() as u64;
+
+        if let Some(max) = options.max_download_bytes {
+            if downloaded > max {
+                return Err(io::Error::other(format!(
+                    "download exceeded the {max} byte limit"
+                )));
+            }
+        }
+        if let Some(progress) = &options.progress {
+            progress(Progress { downloaded, total });
+        }
+
+        Ok(chunk)
+    });
+
+    let gzip = GzipDecoder::new(StreamReader::new(body));
+    let mut archive = Archive::new(gzip);
+    let mut entries = archive.entries()?;
+
+    tokio::fs::create_dir_all(dest).await?;
+
+    let mut unpacked = 0;
+    while let Some(mut entry) = entries.try_next().await? {
+        let path = entry.path()?.into_owned();
+        if !is_contained(&path) {
+            return Err(Error::Archive(format!(
+                "refusing to unpack {}",
+                path.display()
+            )));
+        }
+
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() || kind.is_hard_link() {
+            let target = entry.link_name()?.unwrap_or_default().into_owned();
+            // symlinks resolve relative to their own directory, hard links to the archive root
+            let resolved = match (kind.is_symlink(), path.parent()) {
+                (true, Some(parent)) => parent.join(&target),
+                _ => target.clone(),
+            };
+            if target.is_absolute() || !is_contained(&resolved) {
+                return Err(Error::Archive(format!(
+                    "refusing to unpack link {} -> {}",
+                    path.display(),
+                    target.display()
+                )));
+            }
+        }
+
+        unpacked += entry.header().size()?;
+        if let Some(max) = options.max_unpacked_bytes {
+            if unpacked > max {
+                return Err(Error::Archive(format!(
+                    "unpacked size exceeded the {max} byte limit"
+                )));
+            }
+        }
+
+        entry.unpack_in(dest).await?;
+    }
+
+    Ok(())
+}
+
+/// Whether `path` stays inside the directory it is relative to once `..` components are applied.
+fn is_contained(path: &Path) -> bool {
+    let mut depth = 0usize;
+
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+
+    true
+}
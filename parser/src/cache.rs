@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+
+use crate::archive::FetchOptions;
+use crate::source::{is_commit_sha, Error, Repository, SourceProvider};
+
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Fetched sources on disk, laid out as `<root>/<provider>/<owner>/<name>/<sha>`, where
+/// `<provider>` is the provider's [`SourceProvider::cache_key`], e.g. `gitlab@gitlab.example.com`.
+///
+/// Entries are only ever written once: a fetch unpacks into a `.partial` directory and renames it
+/// into place when complete, so a present entry is always a full checkout of that commit.
+pub struct SourceCache {
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub provider: String,
+    pub repo: Repository,
+    pub sha: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub fetched_at: SystemTime,
+}
+
+impl CacheEntry {
+    /// The root of the repository's sources: archives from forges wrap everything in a single
+    /// `<owner>-<name>-<sha>`-style directory, which is skipped so paths are stable across commits.
+    pub fn source_root(&self) -> io::Result<PathBuf> {
+        let mut entries = fs::read_dir(&self.path)?.collect::<io::Result<Vec<_>>>()?;
+
+        match entries.pop() {
+            Some(entry) if entries.is_empty() && entry.file_type()?.is_dir() => Ok(entry.path()),
+            _ => Ok(self.path.clone()),
+        }
+    }
+}
+
+impl SourceCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// `$DUCKYDUCK_CACHE_DIR`, falling back to `duckyduck/sources` in the user's cache directory.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("DUCKYDUCK_CACHE_DIR") {
+            return PathBuf::from(dir);
+        }
+
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from(".cache"))
+            .join("duckyduck")
+            .join("sources")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, provider: &str, repo: &Repository, sha: &str) -> PathBuf {
+        self.root
+            .join(provider)
+            .join(&repo.owner)
+            .join(&repo.name)
+            .join(sha)
+    }
+
+    /// Returns the cached sources of `repo` at `reference`, fetching them first if needed.
+    ///
+    /// A full commit SHA as the reference is looked up without touching the network.
+    pub async fn fetch(
+        &self,
+        provider: &impl SourceProvider,
+        repo: &Repository,
+        reference: Option<&str>,
+        options: &FetchOptions,
+    ) -> Result<CacheEntry, Error> {
+        let sha = match reference {
+            Some(sha) if is_commit_sha(sha) => sha.to_string(),
+            reference => provider.resolve_commit(repo, reference).await?,
+        };
+
+        let key = provider.cache_key();
+        let path = self.path(&key, repo, &sha);
+        if !path.is_dir() {
+            let partial = path.with_file_name(format!("{sha}{PARTIAL_SUFFIX}"));
+            if partial.exists() {
+                tokio::fs::remove_dir_all(&partial).await?;
+            }
+            if let Some(parent) = partial.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            if let Err(err) = provider.fetch(repo, &sha, &partial, options).await {
+                let _ = tokio::fs::remove_dir_all(&partial).await;
+                return Err(err);
+            }
+            tokio::fs::rename(&partial, &path).await?;
+        }
+
+        Ok(self.entry(&key, repo.clone(), sha, path)?)
+    }
+
+    /// Returns the entry for `repo` at `sha` if it has been fetched before.
+    pub fn get(
+        &self,
+        provider: &str,
+        repo: &Repository,
+        sha: &str,
+    ) -> io::Result<Option<CacheEntry>> {
+        let path = self.path(provider, repo, sha);
+        if !path.is_dir() {
+            return Ok(None);
+        }
+
+        self.entry(provider, repo.clone(), sha.to_string(), path)
+            .map(Some)
+    }
+
+    /// Lists every complete entry in the cache.
+    pub fn entries(&self) -> io::Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+
+        for checkout in self.checkouts()? {
+            let sha = file_name(&checkout);
+            if sha.ends_with(PARTIAL_SUFFIX) {
+                continue;
+            }
+
+            let mut parents = checkout.ancestors().skip(1).map(file_name);
+            let (name, owner, provider) = (
+                parents.next().unwrap_or_default(),
+                parents.next().unwrap_or_default(),
+                parents.next().unwrap_or_default(),
+            );
+
+            entries.push(self.entry(&provider, Repository::new(owner, name), sha, checkout)?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes entries, returning what was removed.
+    ///
+    /// With `keep_latest`, the most recently fetched entries of each repository are kept.
+    /// With `older_than`, only entries fetched longer ago than that are removed.
+    /// Interrupted (`.partial`) fetches are always cleaned up.
+    pub fn prune(
+        &self,
+        keep_latest: Option<usize>,
+        older_than: Option<Duration>,
+    ) -> io::Result<Vec<CacheEntry>> {
+        self.remove_partial()?;
+
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.fetched_at));
+
+        let now = SystemTime::now();
+        let mut seen: Vec<(String, Repository)> = Vec::new();
+        let mut removed = Vec::new();
+
+        for entry in entries {
+            let key = (entry.provider.clone(), entry.repo.clone());
+            let newer = seen.iter().filter(|seen| **seen == key).count();
+            seen.push(key);
+
+            if keep_latest.is_some_and(|keep| newer < keep) {
+                continue;
+            }
+            if let Some(older_than) = older_than {
+                let age = now.duration_since(entry.fetched_at).unwrap_or_default();
+                if age < older_than {
+                    continue;
+                }
+            }
+
+            fs::remove_dir_all(&entry.path)?;
+            removed.push(entry);
+        }
+
+        Ok(removed)
+    }
+
+    fn remove_partial(&self) -> io::Result<()> {
+        for checkout in self.checkouts()? {
+            if file_name(&checkout).ends_with(PARTIAL_SUFFIX) {
+                fs::remove_dir_all(checkout)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every `<provider>/<owner>/<name>/<sha>` directory, including partial ones.
+    fn checkouts(&self) -> io::Result<Vec<PathBuf>> {
+        let mut dirs = vec![self.root.clone()];
+        for _ in 0..4 {
+            let mut children = Vec::new();
+            for dir in dirs {
+                children.extend(subdirs(&dir)?);
+            }
+            dirs = children;
+        }
+
+        Ok(dirs)
+    }
+
+    fn entry(
+        &self,
+        provider: &str,
+        repo: Repository,
+        sha: String,
+        path: PathBuf,
+    ) -> io::Result<CacheEntry> {
+        Ok(CacheEntry {
+            provider: provider.to_string(),
+            repo,
+            sha,
+            size: dir_size(&path)?,
+            fetched_at: fs::metadata(&path)?.modified()?,
+            path,
+        })
+    }
+}
+
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use crate::TCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiscussionKind {
+    Issue,
+    PullRequest,
+    ReviewComment,
+}
+
+impl fmt::Display for DiscussionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Issue => write!(f, "Issue"),
+            Self::PullRequest => write!(f, "Pull Request"),
+            Self::ReviewComment => write!(f, "Review Comment"),
+        }
+    }
+}
+
+/// An issue, pull request or review comment, along with the files and symbols it refers to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TDiscussion {
+    pub kind: DiscussionKind,
+    pub repo: String,
+    /// The issue or pull request number (for review comments, the pull request they were left on).
+    pub number: u64,
+    pub title: String,
+    pub body: Option<String>,
+    pub author: Option<String>,
+    pub url: String,
+    pub state: Option<String>,
+    pub created_at: String,
+    pub comments: Vec<String>,
+    /// The diff hunk a review comment was left on.
+    pub snippet: Option<String>,
+    /// The line a review comment was left on.
+    pub line: Option<u64>,
+    pub files: Vec<String>,
+    pub symbols: Vec<String>,
+}
+
+impl TDiscussion {
+    fn text(&self) -> String {
+        let mut text = self.title.clone();
+        for part in self.body.iter().chain(&self.comments).chain(&self.snippet) {
+            text.push('\n');
+            text.push_str(part);
+        }
+
+        text
+    }
+}
+
+/// The name a symbol is referred to by: `Struct::method` for methods, otherwise just the name.
+pub fn symbol_name(code: &TCode) -> String {
+    match code
+        .context
+        .as_ref()
+        .and_then(|ctx| ctx.struct_name.as_ref())
+    {
+        Some(struct_name) => format!("{struct_name}::{}", code.name),
+        None => code.name.clone(),
+    }
+}
+
+/// Links discussions to the files and symbols of `code` they mention.
+///
+/// Symbols are matched when they appear as inline code or in a code block (so that short names
+/// like `new` aren't matched in prose), with methods needing to be qualified (`Struct::new`).
+/// Review comments are also linked to the symbol spanning the line they were left on.
+pub fn link_discussions(discussions: &mut [TDiscussion], code: &[TCode]) {
+    let file_path = |code: &TCode| {
+        code.context
+            .as_ref()
+            .and_then(|ctx| ctx.file_path.clone())
+            .unwrap_or_default()
+    };
+    let paths: HashSet<String> = code.iter().map(file_path).collect();
+
+    for discussion in discussions {
+        let text = discussion.text();
+        let mentioned = code_identifiers(&text);
+
+        for word in text.split_whitespace() {
+            let word = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '/' && c != '.');
+            if !word.ends_with(".rs") || discussion.files.iter().any(|f| f == word) {
+                continue;
+            }
+            // a bare `lib.rs` could be any crate's, so only mentions of a single file are linked
+            if paths.iter().filter(|path| same_file(path, word)).count() == 1 {
+                discussion.files.push(word.to_string());
+            }
+        }
+
+        let mut symbols: HashSet<String> = discussion.symbols.drain(..).collect();
+        for code in code {
+            let name = symbol_name(code);
+            if mentioned.contains(&name) {
+                symbols.insert(name);
+                continue;
+            }
+
+            let path = file_path(code);
+            if let Some(line) = discussion.line {
+                let in_file = discussion.files.iter().any(|file| same_file(&path, file));
+                if in_file && (code.line_from as u64..=code.line_to as u64).contains(&line) {
+                    symbols.insert(name);
+                }
+            }
+        }
+
+        discussion.symbols = symbols.into_iter().collect();
+        discussion.symbols.sort();
+    }
+}
+
+/// Whether two paths relative to (possibly different) roots point at the same file, comparing
+/// whole components so that `b.rs` doesn't match `src/ab.rs`.
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    !a.as_os_str().is_empty() && !b.as_os_str().is_empty() && (a.ends_with(b) || b.ends_with(a))
+}
+
+/// Identifiers and paths (`foo`, `Foo::bar`) appearing in inline code or code blocks.
+fn code_identifiers(text: &str) -> HashSet<String> {
+    let mut identifiers = HashSet::new();
+
+    // every odd segment between backticks is code, whether inline or fenced
+    for code in text.split('`').skip(1).step_by(2) {
+        let mut current = String::new();
+        for c in code.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() || c == '_' || c == ':' {
+                current.push(c);
+                continue;
+            }
+
+            let ident = current.trim_matches(':');
+            if !ident.is_empty() {
+                identifiers.insert(ident.to_string());
+                // `crate::module::Struct::method` should still match `Struct::method`
+                let segments: Vec<&str> = ident.split("::").collect();
+                for start in 1..segments.len() {
+                    identifiers.insert(segments[start..].join("::"));
+                }
+            }
+            current.clear();
+        }
+    }
+
+    identifiers
+}
@@ -0,0 +1,212 @@
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+
+use tokio::process::Command;
+
+use crate::archive::{FetchOptions, Progress};
+use crate::source::{cache_key, is_commit_sha, Error, Repository, SourceProvider};
+
+/// Any git remote reachable by the `git` binary: an HTTPS or SSH URL, or a local (bare) repository.
+///
+/// The URL may contain `{owner}` and `{name}` placeholders, which are filled in from the
+/// repository being fetched, e.g. `https://git.example.com/{owner}/{name}.git`.
+pub struct GitRemote {
+    url: String,
+}
+
+impl GitRemote {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+
+    /// The URL with its placeholders filled in a single pass, so an owner or name that contains a
+    /// placeholder itself is left as it is.
+    fn remote_url(&self, repo: &Repository) -> String {
+        let mut url = String::with_capacity(self.url.len());
+        let mut rest = self.url.as_str();
+
+        while let Some(start) = rest.find('{') {
+            url.push_str(&rest[..start]);
+            let after = &rest[start..];
+            if let Some(tail) = after.strip_prefix("{owner}") {
+                url.push_str(&repo.owner);
+                rest = tail;
+            } else if let Some(tail) = after.strip_prefix("{name}") {
+                url.push_str(&repo.name);
+                rest = tail;
+            } else {
+                url.push('{');
+                rest = &after[1..];
+            }
+        }
+        url.push_str(rest);
+
+        url
+    }
+}
+
+async fn git<I, S>(args: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("git").args(args).output().await?;
+
+    if !output.status.success() {
+        return Err(Error::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+impl SourceProvider for GitRemote {
+    fn kind(&self) -> &'static str {
+        "git"
+    }
+
+    fn cache_key(&self) -> String {
+        cache_key(self.kind(), &self.url)
+    }
+
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error> {
+        let reference = reference.unwrap_or("HEAD");
+        // `ls-remote` only knows about refs, so full SHAs are taken as-is
+        if is_commit_sha(reference) {
+            return Ok(reference.to_string());
+        }
+
+        let url = self.remote_url(repo);
+        let peeled = format!("{reference}^{{}}");
+        let output = git(["ls-remote", "--", &url, reference, &peeled]).await?;
+
+        let refs: Vec<(&str, &str)> = output
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .collect();
+        let (sha, name) = refs
+            .first()
+            .ok_or_else(|| Error::NotFound(format!("{reference} in {url}")))?;
+
+        // an annotated tag points at a tag object, listed with the commit it tags as `<tag>^{}`
+        let peeled = format!("{name}^{{}}");
+        let commit = refs
+            .iter()
+            .find(|(_, name)| *name == peeled)
+            .map_or(*sha, |(sha, _)| *sha);
+
+        Ok(commit.to_string())
+    }
+
+    /// git doesn't report how much it has downloaded, so the limits in `options` are checked once
+    /// the clone is done, before anything is checked out.
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error> {
+        let url = self.remote_url(repo);
+
+        git([
+            OsStr::new("clone"),
+            OsStr::new("--quiet"),
+            OsStr::new("--no-checkout"),
+            OsStr::new("--"),
+            OsStr::new(&url),
+            dest.as_os_str(),
+        ])
+        .await?;
+
+        let downloaded = dir_size(&dest.join(".git")).await?;
+        if let Some(progress) = &options.progress {
+            progress(Progress {
+                downloaded,
+                total: Some(downloaded),
+            });
+        }
+        if let Some(max) = options.max_download_bytes {
+            if downloaded > max {
+                return Err(Error::Git(format!(
+                    "clone is {downloaded} bytes, over the {max} byte limit"
+                )));
+            }
+        }
+
+        if let Some(max) = options.max_unpacked_bytes {
+            let unpacked = tree_size(dest, sha).await?;
+            if unpacked > max {
+                return Err(Error::Git(format!(
+                    "checkout would be {unpacked} bytes, over the {max} byte limit"
+                )));
+            }
+        }
+
+        git([
+            OsStr::new("-C"),
+            dest.as_os_str(),
+            OsStr::new("checkout"),
+            OsStr::new("--quiet"),
+            OsStr::new("--detach"),
+            OsStr::new(sha),
+        ])
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// The size of the files under `dir`, in bytes.
+async fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// The size of the files in the tree of commit `sha`, in bytes, read from the clone in `dir`.
+async fn tree_size(dir: &Path, sha: &str) -> Result<u64, Error> {
+    let output = git([
+        OsStr::new("-C"),
+        dir.as_os_str(),
+        OsStr::new("ls-tree"),
+        OsStr::new("-r"),
+        OsStr::new("-l"),
+        OsStr::new(sha),
+    ])
+    .await?;
+
+    // each line is `<mode> <type> <object> <size>\t<path>`, with `-` as the size of submodules
+    let size = output
+        .lines()
+        .filter_map(|line| {
+            line.split('\t')
+                .next()?
+                .split_whitespace()
+                .nth(3)?
+                .parse::<u64>()
+                .ok()
+        })
+        .sum();
+
+    Ok(size)
+}
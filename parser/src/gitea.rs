@@ -0,0 +1,101 @@
+use std::{io, path::Path};
+
+use futures::TryStreamExt;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+
+use crate::archive::{unpack_tarball, FetchOptions};
+use crate::source::{cache_key, Error, Repository, SourceProvider};
+
+/// Gitea (and Forgejo) using the v1 REST API.
+pub struct Gitea {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    sha: String,
+}
+
+impl Gitea {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    /// Sets an access token, sent as `Authorization: token <token>`.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    fn repo_url(&self, repo: &Repository) -> String {
+        format!(
+            "{}/api/v1/repos/{}/{}",
+            self.base_url, repo.owner, repo.name
+        )
+    }
+
+    fn get(&self, url: String) -> RequestBuilder {
+        let req = self.client.get(url);
+        match &self.token {
+            Some(token) => req.header("Authorization", format!("token {token}")),
+            None => req,
+        }
+    }
+}
+
+impl SourceProvider for Gitea {
+    fn kind(&self) -> &'static str {
+        "gitea"
+    }
+
+    fn cache_key(&self) -> String {
+        cache_key(self.kind(), &self.base_url)
+    }
+
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error> {
+        let mut req = self
+            .get(format!("{}/commits", self.repo_url(repo)))
+            .query(&[("limit", "1"), ("stat", "false")]);
+        if let Some(reference) = reference {
+            req = req.query(&[("sha", reference)]);
+        }
+
+        let commits: Vec<Commit> = req.send().await?.error_for_status()?.json().await?;
+
+        commits
+            .into_iter()
+            .next()
+            .map(|commit| commit.sha)
+            .ok_or_else(|| Error::NotFound(format!("commits for {repo}")))
+    }
+
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error> {
+        let res = self
+            .get(format!("{}/archive/{sha}.tar.gz", self.repo_url(repo)))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let total = res.content_length();
+        let body = res.bytes_stream().map_err(io::Error::other);
+
+        unpack_tarball(body, total, dest, options).await
+    }
+}
@@ -1,54 +1,346 @@
-use flate2::read::GzDecoder;
-use std::io::{Cursor, Read};
-use std::{path::Path, sync::Arc};
-use tokio_tar::Archive;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{collections::HashMap, io, path::Path, sync::Arc};
 
+use futures::TryStreamExt;
 use http_body_util::BodyExt;
-use octocrab::Octocrab;
+use octocrab::models::IssueState;
+use octocrab::{params::State, Octocrab, Page};
+use serde::de::DeserializeOwned;
+
+use crate::archive::{unpack_tarball, FetchOptions};
+use crate::discussion::{DiscussionKind, TDiscussion};
+use crate::source::{cache_key, Error, Repository, SourceProvider};
+
+/// How many times a request is retried after waiting out GitHub's rate limit.
+const RATE_LIMIT_RETRIES: usize = 3;
 
 pub struct GitHub {
     ctx: Arc<Octocrab>,
+    /// Set when talking to another instance than github.com.
+    base_url: Option<String>,
+    /// Told how long requests wait whenever the rate limit is hit.
+    on_rate_limit: Option<Arc<dyn Fn(Duration) + Send + Sync>>,
 }
 
 impl GitHub {
     pub fn new() -> Self {
         Self {
             ctx: octocrab::instance(),
+            base_url: None,
+            on_rate_limit: None,
+        }
+    }
+
+    /// Authenticates with a personal access token, which raises the rate limit considerably.
+    pub fn with_token(token: String) -> Result<Self, Error> {
+        let ctx = Octocrab::builder().personal_token(token).build()?;
+
+        Ok(Self {
+            ctx: Arc::new(ctx),
+            base_url: None,
+            on_rate_limit: None,
+        })
+    }
+
+    /// Talks to the API at `base_url` instead of api.github.com, e.g. that of a GitHub Enterprise
+    /// Server at `https://github.example.com/api/v3`.
+    pub fn with_base_url(base_url: &str, token: Option<String>) -> Result<Self, Error> {
+        let mut builder = Octocrab::builder().base_uri(base_url)?;
+        if let Some(token) = token {
+            builder = builder.personal_token(token);
+        }
+
+        Ok(Self {
+            ctx: Arc::new(builder.build()?),
+            base_url: Some(base_url.to_string()),
+            on_rate_limit: None,
+        })
+    }
+
+    /// Authenticates with `GITHUB_TOKEN` if it is set, otherwise makes unauthenticated requests.
+    pub fn from_env() -> Result<Self, Error> {
+        match std::env::var("GITHUB_TOKEN") {
+            Ok(token) => Self::with_token(token),
+            Err(_) => Ok(Self::new()),
+        }
+    }
+
+    /// Calls `notify` with how long requests are paused each time the rate limit is hit.
+    pub fn on_rate_limit(mut self, notify: impl Fn(Duration) + Send + Sync + 'static) -> Self {
+        self.on_rate_limit = Some(Arc::new(notify));
+        self
+    }
+
+    /// Fetches every issue, pull request and pull request review comment of `repo`.
+    pub async fn fetch_discussions(&self, repo: &Repository) -> Result<Vec<TDiscussion>, Error> {
+        let mut discussions = self.fetch_issues(repo).await?;
+        let pull_requests = self.fetch_pull_requests(repo).await?;
+
+        let titles: HashMap<u64, String> = pull_requests
+            .iter()
+            .map(|pr| (pr.number, pr.title.clone()))
+            .collect();
+        discussions.extend(pull_requests);
+        discussions.extend(self.fetch_review_comments(repo, &titles).await?);
+
+        Ok(discussions)
+    }
+
+    async fn fetch_issues(&self, repo: &Repository) -> Result<Vec<TDiscussion>, Error> {
+        let issues = self.ctx.issues(&repo.owner, &repo.name);
+        let first = self
+            .with_rate_limit(|| issues.list().state(State::All).per_page(100u8).send())
+            .await?;
+
+        let mut discussions = Vec::new();
+        for issue in self.all_pages(first).await? {
+            // pull requests are listed as issues too, but are fetched separately
+            if issue.pull_request.is_some() {
+                continue;
+            }
+
+            let comments = if issue.comments > 0 {
+                self.fetch_comments(repo, issue.number).await?
+            } else {
+                Vec::new()
+            };
+
+            discussions.push(TDiscussion {
+                kind: DiscussionKind::Issue,
+                repo: repo.to_string(),
+                number: issue.number,
+                title: issue.title,
+                body: issue.body,
+                author: Some(issue.user.login),
+                url: issue.html_url.to_string(),
+                state: Some(state_name(&issue.state)),
+                created_at: issue.created_at.to_rfc3339(),
+                comments,
+                snippet: None,
+                line: None,
+                files: Vec::new(),
+                symbols: Vec::new(),
+            });
         }
+
+        Ok(discussions)
+    }
+
+    async fn fetch_pull_requests(&self, repo: &Repository) -> Result<Vec<TDiscussion>, Error> {
+        let pulls = self.ctx.pulls(&repo.owner, &repo.name);
+        let first = self
+            .with_rate_limit(|| pulls.list().state(State::All).per_page(100u8).send())
+            .await?;
+
+        let mut discussions = Vec::new();
+        for pr in self.all_pages(first).await? {
+            let files = self.with_rate_limit(|| pulls.list_files(pr.number)).await?;
+            let files = self
+                .all_pages(files)
+                .await?
+                .into_iter()
+                .map(|file| file.filename)
+                .collect();
+
+            discussions.push(TDiscussion {
+                kind: DiscussionKind::PullRequest,
+                repo: repo.to_string(),
+                number: pr.number,
+                title: pr.title.unwrap_or_default(),
+                body: pr.body,
+                author: pr.user.map(|user| user.login),
+                url: pr.html_url.map(|url| url.to_string()).unwrap_or(pr.url),
+                state: pr.state.as_ref().map(state_name),
+                created_at: pr.created_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+                comments: self.fetch_comments(repo, pr.number).await?,
+                snippet: None,
+                line: None,
+                files,
+                symbols: Vec::new(),
+            });
+        }
+
+        Ok(discussions)
     }
 
-    pub async fn fetch_repo(&self, org: &str, repo: &str) -> Vec<u8> {
-        let repo = self.ctx.repos(org, repo);
+    async fn fetch_review_comments(
+        &self,
+        repo: &Repository,
+        titles: &HashMap<u64, String>,
+    ) -> Result<Vec<TDiscussion>, Error> {
+        let pulls = self.ctx.pulls(&repo.owner, &repo.name);
+        let first = self
+            .with_rate_limit(|| pulls.list_comments(None).per_page(100u8).send())
+            .await?;
 
-        let latest_commit = repo
-            .list_commits()
+        let discussions = self
+            .all_pages(first)
+            .await?
+            .into_iter()
+            .map(|comment| {
+                // html_url looks like https://github.com/<owner>/<repo>/pull/<number>#discussion_r<id>
+                let number = comment
+                    .html_url
+                    .split("/pull/")
+                    .nth(1)
+                    .and_then(|rest| rest.split('#').next()?.parse().ok())
+                    .unwrap_or_default();
+
+                TDiscussion {
+                    kind: DiscussionKind::ReviewComment,
+                    repo: repo.to_string(),
+                    number,
+                    title: titles
+                        .get(&number)
+                        .cloned()
+                        .unwrap_or_else(|| format!("#{number}")),
+                    body: Some(comment.body),
+                    author: comment.user.map(|user| user.login),
+                    url: comment.html_url,
+                    state: None,
+                    created_at: comment.created_at.to_rfc3339(),
+                    comments: Vec::new(),
+                    snippet: Some(comment.diff_hunk),
+                    line: comment.line.or(comment.original_line),
+                    files: vec![comment.path],
+                    symbols: Vec::new(),
+                }
+            })
+            .collect();
+
+        Ok(discussions)
+    }
+
+    /// Comments on an issue or pull request conversation, as `author: body`.
+    async fn fetch_comments(&self, repo: &Repository, number: u64) -> Result<Vec<String>, Error> {
+        let issues = self.ctx.issues(&repo.owner, &repo.name);
+        let first = self
+            .with_rate_limit(|| issues.list_comments(number).per_page(100u8).send())
+            .await?;
+
+        let comments = self
+            .all_pages(first)
+            .await?
+            .into_iter()
+            .filter_map(|comment| Some(format!("{}: {}", comment.user.login, comment.body?)))
+            .collect();
+
+        Ok(comments)
+    }
+
+    async fn all_pages<T: DeserializeOwned>(&self, mut page: Page<T>) -> Result<Vec<T>, Error> {
+        let mut items = page.take_items();
+
+        loop {
+            let next = page.next.clone();
+            match self.with_rate_limit(|| self.ctx.get_page(&next)).await? {
+                Some(mut next_page) => {
+                    items.append(&mut next_page.take_items());
+                    page = next_page;
+                }
+                None => return Ok(items),
+            }
+        }
+    }
+
+    /// Runs `request`, waiting for the rate limit to reset and retrying whenever GitHub reports it
+    /// has been exceeded.
+    async fn with_rate_limit<T, F, Fut>(&self, mut request: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = octocrab::Result<T>>,
+    {
+        for _ in 0..RATE_LIMIT_RETRIES {
+            match request().await {
+                Err(octocrab::Error::GitHub { source, .. })
+                    if source.message.to_lowercase().contains("rate limit") =>
+                {
+                    let reset = self.ctx.ratelimit().get().await?.resources.core.reset;
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+
+                    // secondary rate limits don't show up in the core reset time, so always back off a bit
+                    let wait = Duration::from_secs(reset.saturating_sub(now).max(60));
+                    if let Some(notify) = &self.on_rate_limit {
+                        notify(wait);
+                    }
+                    tokio::time::sleep(wait).await;
+                }
+                res => return Ok(res?),
+            }
+        }
+
+        Err(Error::Request(
+            "GitHub rate limit still exceeded after retrying".to_string(),
+        ))
+    }
+}
+
+fn state_name(state: &IssueState) -> String {
+    match state {
+        IssueState::Open => "open",
+        IssueState::Closed => "closed",
+        _ => "unknown",
+    }
+    .to_string()
+}
+
+impl SourceProvider for GitHub {
+    fn kind(&self) -> &'static str {
+        "github"
+    }
+
+    fn cache_key(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => cache_key(self.kind(), base_url),
+            None => self.kind().to_string(),
+        }
+    }
+
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error> {
+        let repos = self.ctx.repos(&repo.owner, &repo.name);
+
+        let mut commits = repos.list_commits().per_page(1u8);
+        if let Some(reference) = reference {
+            commits = commits.sha(reference);
+        }
+
+        commits
             .send()
-            .await
-            .unwrap()
+            .await?
             .into_iter()
             .next()
-            .unwrap()
-            .sha;
-
-        repo.download_tarball(latest_commit)
-            .await
-            .unwrap()
-            .into_body()
-            .collect()
-            .await
-            .unwrap()
-            .to_bytes()
-            .to_vec()
+            .map(|commit| commit.sha)
+            .ok_or_else(|| Error::NotFound(format!("commits for {repo}")))
     }
 
-    pub async fn unpack_repo<P: AsRef<Path>>(&self, path: P, bytes: Vec<u8>) {
-        let mut gzip = GzDecoder::new(Cursor::new(bytes));
-        let mut decompressed_bytes = Vec::new();
-        gzip.read_to_end(&mut decompressed_bytes).unwrap();
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error> {
+        let res = self
+            .ctx
+            .repos(&repo.owner, &repo.name)
+            .download_tarball(sha.to_string())
+            .await?;
 
-        let mut ar = Archive::new(Cursor::new(decompressed_bytes));
+        let total = res
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse().ok());
+        let body = res.into_body().into_data_stream().map_err(io::Error::other);
 
-        ar.unpack(path).await.unwrap();
+        unpack_tarball(body, total, dest, options).await
     }
 }
 
@@ -0,0 +1,110 @@
+use std::{io, path::Path};
+
+use futures::TryStreamExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+
+use crate::archive::{unpack_tarball, FetchOptions};
+use crate::source::{cache_key, Error, Repository, SourceProvider};
+
+/// GitLab (gitlab.com or self-hosted) using the v4 REST API.
+pub struct GitLab {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    id: String,
+}
+
+impl GitLab {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    /// Sets a personal access token, sent as the `PRIVATE-TOKEN` header.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    fn project_url(&self, repo: &Repository) -> String {
+        let id = utf8_percent_encode(&repo.to_string(), NON_ALPHANUMERIC).to_string();
+        format!("{}/api/v4/projects/{id}", self.base_url)
+    }
+
+    fn get(&self, url: String) -> RequestBuilder {
+        let req = self.client.get(url);
+        match &self.token {
+            Some(token) => req.header("PRIVATE-TOKEN", token),
+            None => req,
+        }
+    }
+}
+
+impl Default for GitLab {
+    fn default() -> Self {
+        Self::new("https://gitlab.com")
+    }
+}
+
+impl SourceProvider for GitLab {
+    fn kind(&self) -> &'static str {
+        "gitlab"
+    }
+
+    fn cache_key(&self) -> String {
+        cache_key(self.kind(), &self.base_url)
+    }
+
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error> {
+        let mut req = self
+            .get(format!("{}/repository/commits", self.project_url(repo)))
+            .query(&[("per_page", "1")]);
+        if let Some(reference) = reference {
+            req = req.query(&[("ref_name", reference)]);
+        }
+
+        let commits: Vec<Commit> = req.send().await?.error_for_status()?.json().await?;
+
+        commits
+            .into_iter()
+            .next()
+            .map(|commit| commit.id)
+            .ok_or_else(|| Error::NotFound(format!("commits for {repo}")))
+    }
+
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error> {
+        let res = self
+            .get(format!(
+                "{}/repository/archive.tar.gz",
+                self.project_url(repo)
+            ))
+            .query(&[("sha", sha)])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let total = res.content_length();
+        let body = res.bytes_stream().map_err(io::Error::other);
+
+        unpack_tarball(body, total, dest, options).await
+    }
+}
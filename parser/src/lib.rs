@@ -1,11 +1,22 @@
 use quote::{quote, ToTokens};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 use syn::spanned::Spanned;
-use syn::{ImplItem, Item, ItemEnum, ItemFn, ItemImpl, ItemStruct};
+use syn::{Attribute, ImplItem, Item, ItemEnum, ItemFn, ItemImpl, ItemStruct};
 
+use discussion::{DiscussionKind, TDiscussion};
+
+pub mod archive;
+pub mod cache;
+pub mod discussion;
+pub mod git;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
+pub mod manifest;
+pub mod source;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TContext {
@@ -14,6 +25,15 @@ pub struct TContext {
     pub file_name: Option<String>,
     pub struct_name: Option<String>,
     pub snippet: Option<String>,
+    /// The repository (`owner/name`) the code was indexed from, when synced from one.
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// The package name of the crate the file belongs to, with `-` replaced by `_`.
+    #[serde(default)]
+    pub crate_name: Option<String>,
+    /// The full path of the file's module, e.g. `parser::source`.
+    #[serde(default)]
+    pub module_path: Option<String>,
 }
 
 impl TContext {
@@ -25,6 +45,15 @@ impl TContext {
         }
         self.snippet = Some(snippet);
     }
+
+    /// Whether the file is an integration test or benchmark.
+    fn in_tests(&self) -> bool {
+        self.file_path.as_deref().is_some_and(|path| {
+            Path::new(path)
+                .components()
+                .any(|part| matches!(part.as_os_str().to_str(), Some("tests" | "benches")))
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +77,41 @@ impl fmt::Display for CodeType {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// `pub`
+    Public,
+    /// `pub(crate)`
+    Crate,
+    /// `pub(super)` or `pub(in path)`
+    Restricted,
+    #[default]
+    Private,
+}
+
+impl Visibility {
+    fn new(vis: &syn::Visibility) -> Self {
+        match vis {
+            syn::Visibility::Public(_) => Self::Public,
+            syn::Visibility::Restricted(vis) if vis.path.is_ident("crate") => Self::Crate,
+            syn::Visibility::Restricted(_) => Self::Restricted,
+            syn::Visibility::Inherited => Self::Private,
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Public => write!(f, "public"),
+            Self::Crate => write!(f, "crate"),
+            Self::Restricted => write!(f, "restricted"),
+            Self::Private => write!(f, "private"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TCode {
     pub name: String,
@@ -58,10 +122,67 @@ pub struct TCode {
     pub line_from: usize,
     pub line_to: usize,
     pub context: Option<TContext>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Whether this is a test, or only part of tests and benchmarks.
+    #[serde(default)]
+    pub test: bool,
+}
+
+/// Anything that can be stored in the knowledge base.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Document {
+    Code(TCode),
+    Discussion(TDiscussion),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    Code,
+    Issue,
+    PullRequest,
+    ReviewComment,
+}
+
+impl Document {
+    /// The repository (`owner/name`) the document belongs to, if known.
+    pub fn repo(&self) -> Option<&str> {
+        match self {
+            Self::Code(code) => code.context.as_ref()?.repository.as_deref(),
+            Self::Discussion(discussion) => Some(&discussion.repo),
+        }
+    }
+
+    pub fn kind(&self) -> DocumentKind {
+        match self {
+            Self::Code(_) => DocumentKind::Code,
+            Self::Discussion(discussion) => match discussion.kind {
+                DiscussionKind::Issue => DocumentKind::Issue,
+                DiscussionKind::PullRequest => DocumentKind::PullRequest,
+                DiscussionKind::ReviewComment => DocumentKind::ReviewComment,
+            },
+        }
+    }
+}
+
+impl fmt::Display for DocumentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code => write!(f, "code"),
+            Self::Issue => write!(f, "issue"),
+            Self::PullRequest => write!(f, "pull_request"),
+            Self::ReviewComment => write!(f, "review_comment"),
+        }
+    }
 }
 
 pub fn parse_impl(item: &ItemImpl, context: TContext, lines: &[&str]) -> Vec<TCode> {
     let mut functions = Vec::new();
+    let impl_test = is_test(&item.attrs) || context.in_tests();
+    // methods of trait implementations are as visible as the trait
+    let trait_impl = item.trait_.is_some();
+
     for item in &item.items {
         if let ImplItem::Fn(method) = item {
             let signature = &method.sig;
@@ -86,6 +207,11 @@ pub fn parse_impl(item: &ItemImpl, context: TContext, lines: &[&str]) -> Vec<TCo
                 line_from,
                 line_to,
                 context: Some(context.clone()),
+                visibility: match trait_impl {
+                    true => Visibility::Public,
+                    false => Visibility::new(&method.vis),
+                },
+                test: impl_test || is_test(&method.attrs),
             };
             functions.push(function);
         }
@@ -114,6 +240,8 @@ pub fn parse_enum(item: &ItemEnum, mut context: TContext, lines: &[&str]) -> TCo
         line,
         line_from,
         line_to,
+        visibility: Visibility::new(&item.vis),
+        test: is_test(&item.attrs) || context.in_tests(),
         context: Some(context),
     }
 }
@@ -138,6 +266,8 @@ pub fn parse_struct(item: &ItemStruct, mut context: TContext, lines: &[&str]) ->
         line,
         line_from,
         line_to,
+        visibility: Visibility::new(&item.vis),
+        test: is_test(&item.attrs) || context.in_tests(),
         context: Some(context),
     }
 }
@@ -164,6 +294,8 @@ pub fn parse_fn(item: &ItemFn, mut context: TContext, lines: &[&str]) -> TCode {
         line,
         line_from: line,
         line_to: item.block.span().end().line,
+        visibility: Visibility::new(&item.vis),
+        test: is_test(&item.attrs) || context.in_tests(),
         context: Some(context),
     }
 }
@@ -194,6 +326,68 @@ pub fn parse_item(item: &Item, context: TContext, lines: &[&str]) -> (Vec<TCode>
     (functions, structs)
 }
 
+/// Whether the attributes mark a test (`#[test]`, `#[tokio::test]`, ...) or test-only code
+/// (`#[cfg(test)]`).
+fn is_test(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        let path = attr.path();
+        let is_test_attr = path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "test");
+        let is_cfg_test = path.is_ident("cfg")
+            && attr
+                .parse_args::<syn::Ident>()
+                .is_ok_and(|ident| ident == "test");
+
+        is_test_attr || is_cfg_test
+    })
+}
+
+/// The crate a file belongs to, from the nearest `Cargo.toml` with a package between the file and
+/// `root`: its name, with `-` replaced by `_`, and its directory.
+fn find_crate(root: &Path, file: &Path) -> Option<(String, PathBuf)> {
+    for dir in file.ancestors().skip(1) {
+        if !dir.starts_with(root) {
+            break;
+        }
+
+        let Ok(manifest) = fs::read_to_string(dir.join("Cargo.toml")) else {
+            continue;
+        };
+        let name = manifest.parse::<toml::Table>().ok().and_then(|manifest| {
+            manifest
+                .get("package")?
+                .get("name")?
+                .as_str()
+                .map(String::from)
+        });
+        if let Some(name) = name {
+            return Some((name.replace('-', "_"), dir.to_path_buf()));
+        }
+    }
+
+    None
+}
+
+/// The module path of a file in a crate, e.g. `parser::source` for `parser/src/source.rs`.
+fn module_path(crate_name: &str, crate_dir: &Path, file: &Path) -> String {
+    let relative = file
+        .strip_prefix(crate_dir)
+        .unwrap_or(file)
+        .with_extension("");
+    let relative = relative.strip_prefix("src").unwrap_or(&relative);
+
+    let mut path = vec![crate_name.to_string()];
+    for part in relative.iter().map(|part| part.to_string_lossy()) {
+        if !matches!(part.as_ref(), "lib" | "main" | "mod") {
+            path.push(part.into_owned());
+        }
+    }
+
+    path.join("::")
+}
+
 // one possible implementation of walking a directory only visiting files
 pub fn visit_rs_files(dir: &Path, cb: &mut dyn FnMut(&Path)) -> io::Result<()> {
     if dir.is_dir() {
@@ -215,21 +409,61 @@ pub fn visit_rs_files(dir: &Path, cb: &mut dyn FnMut(&Path)) -> io::Result<()> {
     Ok(())
 }
 
+/// A file that couldn't be read or parsed, given as its path relative to the directory and why.
+pub type Skipped = (PathBuf, String);
+
 /// Processes a directory.
-pub fn process_dir(path: PathBuf) -> Vec<TCode> {
+pub fn process_dir(path: PathBuf) -> io::Result<(Vec<TCode>, Vec<Skipped>)> {
+    process_dir_with(path, &|_| true)
+}
+
+/// Processes the files of a directory for which `filter` returns true, given their path relative
+/// to the directory. Files that can't be read or parsed are skipped and returned alongside the
+/// code, so one bad file doesn't stop the rest from being indexed; only failing to walk the
+/// directory is an error.
+pub fn process_dir_with(
+    path: PathBuf,
+    filter: &dyn Fn(&Path) -> bool,
+) -> io::Result<(Vec<TCode>, Vec<Skipped>)> {
     let mut functions: Vec<TCode> = vec![];
     let mut structs: Vec<TCode> = vec![];
+    let mut skipped: Vec<Skipped> = vec![];
 
     let dir_path = &path;
+    let mut crates: HashMap<PathBuf, Option<(String, PathBuf)>> = HashMap::new();
 
     visit_rs_files(dir_path, &mut |path| {
         let relative_path = path.strip_prefix(dir_path).unwrap();
+        if !filter(relative_path) {
+            return;
+        }
 
-        let file_content = fs::read_to_string(path).unwrap();
+        let file_content = match fs::read_to_string(path) {
+            Ok(file_content) => file_content,
+            Err(err) => {
+                skipped.push((relative_path.to_path_buf(), err.to_string()));
+                return;
+            }
+        };
 
         let lines = file_content.lines().collect::<Vec<&str>>();
 
-        let syntax = syn::parse_file(&file_content).unwrap();
+        let syntax = match syn::parse_file(&file_content) {
+            Ok(syntax) => syntax,
+            Err(err) => {
+                skipped.push((relative_path.to_path_buf(), err.to_string()));
+                return;
+            }
+        };
+
+        let parent = path.parent().unwrap_or(path).to_path_buf();
+        let krate = crates
+            .entry(parent)
+            .or_insert_with(|| find_crate(dir_path, path));
+        let crate_name = krate.as_ref().map(|(name, _)| name.clone());
+        let module_path = krate
+            .as_ref()
+            .map(|(name, dir)| module_path(name, dir, path));
 
         for item in &syntax.items {
             let (mut f, mut s) = parse_item(
@@ -241,24 +475,25 @@ pub fn process_dir(path: PathBuf) -> Vec<TCode> {
                             .unwrap()
                             .file_name()
                             .unwrap_or_default()
-                            .to_str()
-                            .unwrap()
+                            .to_string_lossy()
                             .to_string(),
                     ),
-                    file_path: Some(relative_path.to_str().unwrap().to_string()),
-                    file_name: Some(path.file_name().unwrap().to_str().unwrap().to_string()),
+                    file_path: Some(relative_path.to_string_lossy().to_string()),
+                    file_name: Some(path.file_name().unwrap().to_string_lossy().to_string()),
                     struct_name: None,
                     snippet: None,
+                    repository: None,
+                    crate_name: crate_name.clone(),
+                    module_path: module_path.clone(),
                 },
                 &lines,
             );
             functions.append(&mut f);
             structs.append(&mut s);
         }
-    })
-    .unwrap();
+    })?;
 
     structs.extend_from_slice(&functions);
 
-    structs
+    Ok((structs, skipped))
 }
@@ -0,0 +1,101 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::source::{Error, ProviderKind, Repository};
+
+/// A list of repositories to keep indexed, read from a TOML file such as:
+///
+/// ```toml
+/// [[repository]]
+/// owner = "tokio-rs"
+/// name = "tokio"
+/// ref = "master"
+/// exclude = ["**/tests/**", "benches/**"]
+///
+/// [[repository]]
+/// provider = "gitea"
+/// namespace = "billing"
+/// url = "https://git.example.com"
+/// owner = "platform"
+/// name = "billing"
+/// include = ["crates/**"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    #[serde(rename = "repository", default)]
+    pub repositories: Vec<RepoSource>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoSource {
+    #[serde(default)]
+    pub provider: ProviderKind,
+    /// API base URL of a GitHub Enterprise Server, base URL of a GitLab/Gitea instance, or the
+    /// remote URL for `git`.
+    pub url: Option<String>,
+    pub owner: String,
+    pub name: String,
+    /// Branch, tag or commit to index (defaults to the default branch).
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    /// Globs (relative to the repository root) of files to index. Everything is indexed if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Globs of files to leave out, even if they match `include`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// The namespace to index into, instead of the configured one.
+    pub namespace: Option<String>,
+}
+
+impl Manifest {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+
+        toml::from_str(&contents).map_err(|x| Error::Manifest(x.to_string()))
+    }
+}
+
+impl RepoSource {
+    pub fn repository(&self) -> Repository {
+        Repository::new(&self.owner, &self.name)
+    }
+
+    pub fn file_filter(&self) -> Result<FileFilter, Error> {
+        let include = if self.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&self.include)?)
+        };
+
+        Ok(FileFilter {
+            include,
+            exclude: glob_set(&self.exclude)?,
+        })
+    }
+}
+
+/// The include/exclude rules of a repository.
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    /// Whether a file, given by its path relative to the repository root, should be indexed.
+    pub fn matches(&self, path: &Path) -> bool {
+        let included = self.include.as_ref().is_none_or(|set| set.is_match(path));
+
+        included && !self.exclude.is_match(path)
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|x| Error::Manifest(x.to_string()))?);
+    }
+
+    builder.build().map_err(|x| Error::Manifest(x.to_string()))
+}
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{fmt, io};
+
+use crate::archive::FetchOptions;
+use crate::git::GitRemote;
+use crate::gitea::Gitea;
+use crate::github::GitHub;
+use crate::gitlab::GitLab;
+
+/// A repository on a source provider, identified by its owner (user, org or group) and name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Repository {
+    pub owner: String,
+    pub name: String,
+}
+
+impl Repository {
+    pub fn new(owner: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            owner: owner.into(),
+            name: name.into(),
+        }
+    }
+}
+
+impl fmt::Display for Repository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.owner, self.name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Request(String),
+    NotFound(String),
+    Git(String),
+    Archive(String),
+    Manifest(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(err) => write!(f, "Request failed: {err}"),
+            Self::NotFound(what) => write!(f, "Not found: {what}"),
+            Self::Git(err) => write!(f, "git failed: {err}"),
+            Self::Archive(err) => write!(f, "Invalid archive: {err}"),
+            Self::Manifest(err) => write!(f, "Invalid manifest: {err}"),
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<octocrab::Error> for Error {
+    fn from(err: octocrab::Error) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Request(err.to_string())
+    }
+}
+
+/// Somewhere repository sources can be fetched from: a git forge or a plain git remote.
+#[allow(async_fn_in_trait)]
+pub trait SourceProvider {
+    /// Short name of the provider, e.g. `github`.
+    fn kind(&self) -> &'static str;
+
+    /// Resolves `reference` (a branch, tag or commit) to a commit SHA.
+    /// When no reference is given, the head of the default branch is used.
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error>;
+
+    /// Names the provider in the source cache: its kind, followed by the host of the instance for
+    /// providers that can point at more than one, e.g. `gitlab@gitlab.example.com`, so that
+    /// repositories with the same name on different instances are kept apart.
+    fn cache_key(&self) -> String {
+        self.kind().to_string()
+    }
+
+    /// Fetches the source tree of `repo` at commit `sha` into the `dest` directory.
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    Github,
+    Gitlab,
+    Gitea,
+    /// Any git URL or local bare repository.
+    Git,
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Github => write!(f, "github"),
+            Self::Gitlab => write!(f, "gitlab"),
+            Self::Gitea => write!(f, "gitea"),
+            Self::Git => write!(f, "git"),
+        }
+    }
+}
+
+/// Any of the supported providers, for when which one is used is only known at runtime.
+pub enum AnyProvider {
+    GitHub(GitHub),
+    GitLab(GitLab),
+    Gitea(Gitea),
+    Git(GitRemote),
+}
+
+impl AnyProvider {
+    /// Creates a provider of the given kind, authenticated with `GITHUB_TOKEN`, `GITLAB_TOKEN`
+    /// or `GITEA_TOKEN` when set.
+    ///
+    /// `url` is the API base URL of a GitHub Enterprise Server, the base URL of a GitLab or Gitea
+    /// instance, or the remote URL for git.
+    pub fn new(kind: ProviderKind, url: Option<String>) -> Result<Self, Error> {
+        let token = |var| std::env::var(var).ok();
+
+        let provider = match (kind, url) {
+            (ProviderKind::Github, Some(url)) => {
+                Self::GitHub(GitHub::with_base_url(&url, token("GITHUB_TOKEN"))?)
+            }
+            (ProviderKind::Github, None) => Self::GitHub(GitHub::from_env()?),
+            (ProviderKind::Gitlab, url) => Self::GitLab(
+                url.map(GitLab::new)
+                    .unwrap_or_default()
+                    .with_token(token("GITLAB_TOKEN")),
+            ),
+            (ProviderKind::Gitea, Some(url)) => {
+                Self::Gitea(Gitea::new(url).with_token(token("GITEA_TOKEN")))
+            }
+            (ProviderKind::Git, Some(url)) => Self::Git(GitRemote::new(url)),
+            (kind, None) => return Err(Error::NotFound(format!("a URL for the {kind} provider"))),
+        };
+
+        Ok(provider)
+    }
+}
+
+impl SourceProvider for AnyProvider {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::GitHub(provider) => provider.kind(),
+            Self::GitLab(provider) => provider.kind(),
+            Self::Gitea(provider) => provider.kind(),
+            Self::Git(provider) => provider.kind(),
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        match self {
+            Self::GitHub(provider) => provider.cache_key(),
+            Self::GitLab(provider) => provider.cache_key(),
+            Self::Gitea(provider) => provider.cache_key(),
+            Self::Git(provider) => provider.cache_key(),
+        }
+    }
+
+    async fn resolve_commit(
+        &self,
+        repo: &Repository,
+        reference: Option<&str>,
+    ) -> Result<String, Error> {
+        match self {
+            Self::GitHub(provider) => provider.resolve_commit(repo, reference).await,
+            Self::GitLab(provider) => provider.resolve_commit(repo, reference).await,
+            Self::Gitea(provider) => provider.resolve_commit(repo, reference).await,
+            Self::Git(provider) => provider.resolve_commit(repo, reference).await,
+        }
+    }
+
+    async fn fetch(
+        &self,
+        repo: &Repository,
+        sha: &str,
+        dest: &Path,
+        options: &FetchOptions,
+    ) -> Result<(), Error> {
+        match self {
+            Self::GitHub(provider) => provider.fetch(repo, sha, dest, options).await,
+            Self::GitLab(provider) => provider.fetch(repo, sha, dest, options).await,
+            Self::Gitea(provider) => provider.fetch(repo, sha, dest, options).await,
+            Self::Git(provider) => provider.fetch(repo, sha, dest, options).await,
+        }
+    }
+}
+
+/// Whether `reference` is a full (40 character) commit SHA rather than a branch or tag name.
+pub(crate) fn is_commit_sha(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// `kind@host` for a provider at `url`, or just `kind` if `url` is a local path.
+pub(crate) fn cache_key(kind: &str, url: &str) -> String {
+    match url_host(url) {
+        Some(host) => format!("{kind}@{host}"),
+        None => kind.to_string(),
+    }
+}
+
+/// The host (and port) of a URL such as `https://gitlab.example.com/` or an scp-like remote such as
+/// `git@github.com:{owner}/{name}.git`, usable as a directory name.
+fn url_host(url: &str) -> Option<String> {
+    let authority = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => rest.split('/').next()?,
+        // scp-like remotes put the host before a colon, which local paths don't have
+        None => match url.split_once(':') {
+            Some((host, _)) if !host.contains('/') => host,
+            _ => return None,
+        },
+    };
+
+    let host = authority.rsplit('@').next()?;
+    (!host.is_empty()).then(|| host.replace(':', "_"))
+}
@@ -0,0 +1,371 @@
+//! Runs the providers against a stand-in for each forge's API on a local port, and the git remote
+//! against a local bare repository.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_compression::tokio::write::GzipEncoder;
+use parser::archive::FetchOptions;
+use parser::git::GitRemote;
+use parser::gitea::Gitea;
+use parser::github::GitHub;
+use parser::gitlab::GitLab;
+use parser::source::{AnyProvider, Error, ProviderKind, Repository, SourceProvider};
+use tokio::io::AsyncWriteExt;
+
+const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+const README: &str = "# widget\n";
+
+/// Answers each request with the body registered for its path, or a 404, and records the path
+/// and query of every request.
+struct StandIn {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandIn {
+    fn serve(routes: Vec<(String, Vec<u8>)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                        break;
+                    }
+                }
+
+                let target = request_line.split(' ').nth(1).unwrap_or("/").to_string();
+                let path = target.split('?').next().unwrap_or_default();
+                let response = match routes.iter().find(|(route, _)| route == path) {
+                    Some((_, body)) => (200, body.clone()),
+                    None => (404, b"{\"message\": \"Not Found\"}".to_vec()),
+                };
+                recorded.lock().unwrap().push(target);
+
+                let (status, body) = response;
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Stand-in\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    fn requested(&self, fragment: &str) -> bool {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().any(|request| request.contains(fragment))
+    }
+}
+
+/// A gzipped tarball of a repository with a README, in a top-level directory as forges lay them out.
+async fn tarball() -> Vec<u8> {
+    let mut tar = tokio_tar::Builder::new(Vec::new());
+    let mut header = tokio_tar::Header::new_gnu();
+    header.set_size(README.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, "widget-main/README.md", README.as_bytes())
+        .await
+        .unwrap();
+    let tar = tar.into_inner().await.unwrap();
+
+    let mut gzip = GzipEncoder::new(Vec::new());
+    gzip.write_all(&tar).await.unwrap();
+    gzip.shutdown().await.unwrap();
+    gzip.into_inner()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("parser-test-{name}-{nanos}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn repo() -> Repository {
+    Repository::new("acme", "widget")
+}
+
+/// Resolves `main` and fetches the tarball of the resolved commit into a fresh directory.
+async fn resolve_and_fetch(provider: &impl SourceProvider, name: &str) -> PathBuf {
+    let sha = provider
+        .resolve_commit(&repo(), Some("main"))
+        .await
+        .unwrap();
+    assert_eq!(sha, SHA);
+
+    let dest = scratch_dir(name);
+    provider
+        .fetch(&repo(), &sha, &dest, &FetchOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dest.join("widget-main/README.md")).unwrap(),
+        README
+    );
+
+    dest
+}
+
+#[tokio::test]
+async fn github_resolves_and_fetches() {
+    let commits = format!(
+        r#"[{{
+            "url": "http://localhost/", "sha": "{SHA}", "node_id": "", "html_url": "",
+            "comments_url": "", "author": null, "committer": null, "parents": [],
+            "commit": {{
+                "url": "http://localhost/", "author": null, "committer": null, "message": "",
+                "comment_count": 0, "tree": {{ "sha": "{SHA}", "url": "http://localhost/" }}
+            }}
+        }}]"#
+    );
+    let server = StandIn::serve(vec![
+        (
+            "/repos/acme/widget/commits".to_string(),
+            commits.into_bytes(),
+        ),
+        (format!("/repos/acme/widget/tarball/{SHA}"), tarball().await),
+    ]);
+
+    let github = GitHub::with_base_url(&server.url, None).unwrap();
+    let dest = resolve_and_fetch(&github, "github").await;
+
+    assert!(server.requested("sha=main"));
+    std::fs::remove_dir_all(dest).unwrap();
+}
+
+#[tokio::test]
+async fn gitlab_resolves_and_fetches() {
+    let project = "/api/v4/projects/acme%2Fwidget";
+    let server = StandIn::serve(vec![
+        (
+            format!("{project}/repository/commits"),
+            format!(r#"[{{ "id": "{SHA}" }}]"#).into_bytes(),
+        ),
+        (
+            format!("{project}/repository/archive.tar.gz"),
+            tarball().await,
+        ),
+    ]);
+
+    let gitlab = GitLab::new(&server.url);
+    let dest = resolve_and_fetch(&gitlab, "gitlab").await;
+
+    assert!(server.requested("ref_name=main"));
+    assert!(server.requested(&format!("sha={SHA}")));
+    std::fs::remove_dir_all(dest).unwrap();
+}
+
+#[tokio::test]
+async fn gitea_resolves_and_fetches() {
+    let server = StandIn::serve(vec![
+        (
+            "/api/v1/repos/acme/widget/commits".to_string(),
+            format!(r#"[{{ "sha": "{SHA}" }}]"#).into_bytes(),
+        ),
+        (
+            format!("/api/v1/repos/acme/widget/archive/{SHA}.tar.gz"),
+            tarball().await,
+        ),
+    ]);
+
+    let gitea = Gitea::new(&server.url);
+    let dest = resolve_and_fetch(&gitea, "gitea").await;
+
+    assert!(server.requested("sha=main"));
+    std::fs::remove_dir_all(dest).unwrap();
+}
+
+#[tokio::test]
+async fn gitea_enforces_the_unpacked_limit() {
+    let server = StandIn::serve(vec![(
+        format!("/api/v1/repos/acme/widget/archive/{SHA}.tar.gz"),
+        tarball().await,
+    )]);
+
+    let dest = scratch_dir("gitea-limit");
+    let options = FetchOptions {
+        max_unpacked_bytes: Some(1),
+        ..Default::default()
+    };
+    let res = Gitea::new(&server.url)
+        .fetch(&repo(), SHA, &dest, &options)
+        .await;
+
+    assert!(matches!(res, Err(Error::Archive(_))));
+    std::fs::remove_dir_all(dest).unwrap();
+}
+
+fn run_git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed: {output:?}");
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// A bare repository at `<dir>/acme/widget.git` with one commit on `main`, returning its SHA.
+fn bare_repo(dir: &Path) -> String {
+    let work = dir.join("work");
+    std::fs::create_dir_all(&work).unwrap();
+    run_git(&work, &["init", "--quiet", "--initial-branch=main"]);
+    std::fs::write(work.join("README.md"), README).unwrap();
+    run_git(&work, &["add", "README.md"]);
+    run_git(&work, &["commit", "--quiet", "--message", "Initial commit"]);
+    run_git(
+        &work,
+        &["tag", "--annotate", "v1", "--message", "First release"],
+    );
+
+    let bare = dir.join("acme/widget.git");
+    run_git(
+        dir,
+        &["clone", "--quiet", "--bare", "work", bare.to_str().unwrap()],
+    );
+
+    run_git(&work, &["rev-parse", "HEAD"])
+}
+
+#[tokio::test]
+async fn git_remote_resolves_and_fetches() {
+    let dir = scratch_dir("git");
+    let sha = bare_repo(&dir);
+    let remote = GitRemote::new(format!("{}/{{owner}}/{{name}}.git", dir.display()));
+
+    assert_eq!(remote.resolve_commit(&repo(), None).await.unwrap(), sha);
+    assert_eq!(
+        remote.resolve_commit(&repo(), Some("main")).await.unwrap(),
+        sha
+    );
+    // the commit an annotated tag points at, not the tag object
+    assert_eq!(
+        remote.resolve_commit(&repo(), Some("v1")).await.unwrap(),
+        sha
+    );
+    // taken as a ref to look up, not as an option of `git ls-remote`
+    let option = remote
+        .resolve_commit(&repo(), Some("--upload-pack=false"))
+        .await;
+    assert!(matches!(option, Err(Error::NotFound(_))));
+
+    let dest = dir.join("checkout");
+    remote
+        .fetch(&repo(), &sha, &dest, &FetchOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dest.join("README.md")).unwrap(),
+        README
+    );
+    assert_eq!(run_git(&dest, &["rev-parse", "HEAD"]), sha);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn git_remote_enforces_limits() {
+    let dir = scratch_dir("git-limits");
+    let sha = bare_repo(&dir);
+    let remote = GitRemote::new(format!("{}/{{owner}}/{{name}}.git", dir.display()));
+
+    for (name, options) in [
+        (
+            "download",
+            FetchOptions {
+                max_download_bytes: Some(1),
+                ..Default::default()
+            },
+        ),
+        (
+            "unpacked",
+            FetchOptions {
+                max_unpacked_bytes: Some(1),
+                ..Default::default()
+            },
+        ),
+    ] {
+        let dest = dir.join(name);
+        let res = remote.fetch(&repo(), &sha, &dest, &options).await;
+
+        assert!(matches!(res, Err(Error::Git(_))), "{name}: {res:?}");
+        assert!(!dest.join("README.md").exists());
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// building an Octocrab client needs a runtime
+#[tokio::test]
+async fn cache_keys_include_the_host() {
+    assert_eq!(GitHub::new().cache_key(), "github");
+    assert_eq!(
+        GitHub::with_base_url("https://github.example.com/api/v3", None)
+            .unwrap()
+            .cache_key(),
+        "github@github.example.com"
+    );
+    let enterprise = AnyProvider::new(
+        ProviderKind::Github,
+        Some("https://github.example.com/api/v3".to_string()),
+    )
+    .unwrap();
+    assert_eq!(enterprise.cache_key(), "github@github.example.com");
+    assert_eq!(GitLab::default().cache_key(), "gitlab@gitlab.com");
+    assert_eq!(
+        Gitea::new("http://localhost:3000/").cache_key(),
+        "gitea@localhost_3000"
+    );
+    assert_eq!(
+        GitRemote::new("ssh://git@git.example.com:2222/{owner}/{name}.git").cache_key(),
+        "git@git.example.com_2222"
+    );
+    assert_eq!(
+        GitRemote::new("git@github.com:{owner}/{name}.git").cache_key(),
+        "git@github.com"
+    );
+    assert_eq!(
+        GitRemote::new("/srv/git/{owner}/{name}.git").cache_key(),
+        "git"
+    );
+}
+
+#[tokio::test]
+async fn git_remote_fills_placeholders_once() {
+    let dir = scratch_dir("git-placeholders");
+    let sha = bare_repo(&dir);
+    std::fs::rename(dir.join("acme"), dir.join("{name}")).unwrap();
+    let remote = GitRemote::new(format!("{}/{{owner}}/{{name}}.git", dir.display()));
+
+    // the `{name}` in the owner is kept, not expanded into `widget`
+    let owner = Repository::new("{name}", "widget");
+    assert_eq!(remote.resolve_commit(&owner, None).await.unwrap(), sha);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -2,46 +2,81 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use llms::{phi::TextGeneration, qdrant::Qdrant, Embedder};
+use llms::{
+    config::Config,
+    models::ModelFiles,
+    phi::TextGeneration,
+    rerank::Reranker,
+    store::{AnyStore, CollectionStatus, SearchOptions, VectorStore},
+    Embedder,
+};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
 mod routes;
-use routes::{homepage, prompt, prompt_text};
+use routes::{homepage, prompt, prompt_text, search_json, similar, stats};
 
 #[derive(Clone)]
 pub struct AppState {
-    qdrant: Arc<Qdrant>,
+    store: Arc<AnyStore>,
     embedder: Arc<Embedder>,
+    search: SearchOptions,
+    reranker: Option<Arc<Reranker>>,
     ort: TextGeneration,
+    /// The checkout ranges that aren't indexed are read from, for `/similar`.
+    source_dir: PathBuf,
 }
 
 impl AppState {
-    fn new() -> Self {
-        let qdrant = Qdrant::from_url("http://localhost:6334", None)
-            .expect("Running Qdrant gRPC at localhost:6334");
-        let embedder = Embedder::new().unwrap();
+    async fn new() -> Self {
+        let config = std::env::var_os("DUCKYDUCK_CONFIG");
+        let config = Config::load(config.as_deref().map(Path::new)).unwrap();
+        let store = AnyStore::from_config(&config).unwrap();
+        let embedder = Embedder::from_config(&config).unwrap();
+        let search = SearchOptions::from_config(&config.search);
+        let reranker = Reranker::from_config(&config).unwrap().map(Arc::new);
+
+        match store.ensure_collection(&embedder).await.unwrap() {
+            CollectionStatus::Created => println!("Created collection {}.", store.collection()),
+            CollectionStatus::Outdated(reasons) => println!(
+                "Collection {} should be migrated: {}.",
+                store.collection(),
+                reasons.join(", ")
+            ),
+            CollectionStatus::Ready => {}
+        }
 
-        let qdrant = Arc::new(qdrant);
+        let store = Arc::new(store);
         let embedder = Arc::new(embedder);
-        let ort = TextGeneration::new().unwrap();
+        let files = ModelFiles::new(&config.models).unwrap();
+        let ort = TextGeneration::from_files(&files).unwrap();
+        let source_dir = std::env::var_os("DUCKYDUCK_SOURCE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
 
         Self {
-            qdrant,
+            store,
             embedder,
+            search,
+            reranker,
             ort,
+            source_dir,
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let state = AppState::new();
+    let state = AppState::new().await;
 
     let rtr = Router::new()
         .route("/", get(homepage))
         .route("/prompt", post(prompt))
         .route("/prompt/text", post(prompt_text))
+        .route("/search", post(search_json))
+        .route("/similar", post(similar))
+        .route("/stats", get(stats))
         .with_state(state);
 
     let tcp_listener = TcpListener::bind("127.0.0.1:8000").await.unwrap();
@@ -1,8 +1,18 @@
-use axum::{body::Body, extract::State, response::IntoResponse as AxumResponse, Form, Json};
+use axum::{
+    body::Body, extract::State, http::StatusCode, response::IntoResponse as AxumResponse, Form,
+    Json,
+};
 
-use serde::Deserialize;
+use serde::de::value::StringDeserializer;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Deserializer};
 
-use parser::TCode;
+use llms::config::GroupBy;
+use llms::filter::SearchFilter;
+use llms::similar::{read_range, Seed};
+use llms::stats::Stats;
+use llms::store::{SearchOptions, SearchResult, VectorStore};
+use parser::{CodeType, Document, DocumentKind, Visibility};
 
 use askama::Template;
 use askama_axum::IntoResponse as AskamaResponse;
@@ -12,6 +22,123 @@ use crate::AppState;
 #[derive(Deserialize)]
 pub struct Prompt {
     prompt: String,
+    /// How many results to retrieve before reranking.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    candidates: Option<u64>,
+    /// How many results to keep after reranking.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    top_k: Option<usize>,
+    #[serde(flatten)]
+    page: Page,
+    #[serde(flatten)]
+    filter: FilterForm,
+}
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    prompt: String,
+    candidates: Option<u64>,
+    top_k: Option<usize>,
+    #[serde(flatten)]
+    page: Page,
+    #[serde(default)]
+    filter: SearchFilter,
+}
+
+/// Which page of results to return, and how to group them, overriding the config.
+#[derive(Default, Deserialize)]
+pub struct Page {
+    /// How many results (or groups) to skip.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    offset: Option<u64>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    group_by: Option<GroupBy>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    group_size: Option<u64>,
+}
+
+/// The filters of the prompt form, which each take a single value.
+#[derive(Default, Deserialize)]
+pub struct FilterForm {
+    #[serde(default, deserialize_with = "blank_as_none")]
+    kind: Option<DocumentKind>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    code_type: Option<CodeType>,
+    #[serde(default, rename = "crate", deserialize_with = "blank_as_none")]
+    crate_name: Option<String>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    module: Option<String>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    file: Option<String>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    visibility: Option<Visibility>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    repo: Option<String>,
+    #[serde(default, deserialize_with = "blank_as_none")]
+    test: Option<TestFilter>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TestFilter {
+    Only,
+    Exclude,
+}
+
+impl From<FilterForm> for SearchFilter {
+    fn from(form: FilterForm) -> Self {
+        Self {
+            kinds: form.kind.into_iter().collect(),
+            code_types: form.code_type.into_iter().collect(),
+            crate_name: form.crate_name,
+            module: form.module,
+            file: form.file,
+            visibility: form.visibility,
+            repo: form.repo,
+            test: form.test.map(|test| matches!(test, TestFilter::Only)),
+        }
+    }
+}
+
+/// Form fields that were left empty are submitted as `""`, which should mean "not set".
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Field<T> {
+        Number(T),
+        Text(String),
+    }
+
+    match Option::<Field<T>>::deserialize(deserializer)? {
+        Some(Field::Number(x)) => Ok(Some(x)),
+        Some(Field::Text(text)) if text.trim().is_empty() => Ok(None),
+        Some(Field::Text(text)) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Like `empty_as_none`, for text and choices.
+fn blank_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.trim().is_empty() => {
+            let text: StringDeserializer<D::Error> = text.trim().to_string().into_deserializer();
+            T::deserialize(text).map(Some)
+        }
+        _ => Ok(None),
+    }
 }
 
 #[derive(Template)]
@@ -21,32 +148,158 @@ struct IndexFile;
 #[derive(Template)]
 #[template(path = "table.html")]
 struct TableResult {
-    results: Vec<TCode>,
+    results: Vec<SearchResult>,
 }
 
 pub async fn prompt(
     State(state): State<AppState>,
-    Form(Prompt { prompt }): Form<Prompt>,
-) -> impl AskamaResponse {
+    Form(Prompt {
+        prompt,
+        candidates,
+        top_k,
+        page,
+        filter,
+    }): Form<Prompt>,
+) -> Result<impl AskamaResponse, (StatusCode, String)> {
+    search(&state, prompt, candidates, top_k, page, filter.into())
+        .await
+        .map(|results| TableResult { results })
+        .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x))
+}
+
+/// The search behind the prompt form, as JSON.
+pub async fn search_json(
+    State(state): State<AppState>,
+    Json(SearchRequest {
+        prompt,
+        candidates,
+        top_k,
+        page,
+        filter,
+    }): Json<SearchRequest>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
+    search(&state, prompt, candidates, top_k, page, filter)
+        .await
+        .map(Json)
+        .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x))
+}
+
+#[derive(Deserialize)]
+pub struct SimilarRequest {
+    /// A point ID, `file:line_from-line_to` or a symbol name.
+    seed: String,
+    #[serde(default = "default_vector")]
+    vector: String,
+    limit: Option<u64>,
+    #[serde(default)]
+    filter: SearchFilter,
+}
+
+fn default_vector() -> String {
+    "code".to_string()
+}
+
+/// The nearest neighbours of a symbol or range of lines, leaving out the seed itself. A range that
+/// isn't indexed is read from the checkout and embedded.
+pub async fn similar(
+    State(state): State<AppState>,
+    Json(SimilarRequest {
+        seed,
+        vector,
+        limit,
+        filter,
+    }): Json<SimilarRequest>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
+    let seed = seed
+        .parse::<Seed>()
+        .map_err(|x| (StatusCode::BAD_REQUEST, x.to_string()))?;
+    let mut options = state.search.clone();
+    options.filter = filter;
+    if let Some(limit) = limit {
+        options.limit = limit;
+    }
+
+    let repo = options.filter.repo.as_deref();
+    let seeds = state
+        .store
+        .lookup(&seed, &vector, repo)
+        .await
+        .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x.to_string()))?;
+    let (embedding, exclude) = match (&seed, seeds.first()) {
+        // every point overlapping the range is part of it
+        (Seed::Range { .. }, Some(point)) => {
+            let exclude = seeds.iter().map(|point| point.id.clone()).collect();
+            (point.vector.clone(), exclude)
+        }
+        (_, Some(point)) => (point.vector.clone(), vec![point.id.clone()]),
+        (Seed::Range { file, from, to }, None) => {
+            let text = read_range(&state.source_dir, file, *from, *to)
+                .map_err(|x| (StatusCode::NOT_FOUND, format!("{seed} is not indexed: {x}")))?;
+            let embedding = state
+                .embedder
+                .embed_passage(&vector, text)
+                .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x.to_string()))?;
+            (embedding, Vec::new())
+        }
+        (_, None) => return Err((StatusCode::NOT_FOUND, format!("{seed} is not indexed"))),
+    };
+
+    state
+        .store
+        .similar(&vector, embedding, &exclude, &options)
+        .await
+        .map(Json)
+        .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x.to_string()))
+}
+
+async fn search(
+    state: &AppState,
+    prompt: String,
+    candidates: Option<u64>,
+    top_k: Option<usize>,
+    page: Page,
+    filter: SearchFilter,
+) -> Result<Vec<SearchResult>, String> {
     let embedding = state
         .embedder
-        .embed_prompt(prompt)
-        .inspect_err(|x| println!("Something went wrong: {x}"))
-        .unwrap();
+        .embed_prompt(prompt.clone())
+        .map_err(|x| x.to_string())?;
 
-    let results = state
-        .qdrant
-        .search(embedding)
+    let mut options = state.search.clone();
+    options.filter = filter;
+    options.offset = page.offset.unwrap_or_default();
+    if let Some(group_by) = page.group_by {
+        options.group_by = Some(group_by);
+    }
+    if let Some(group_size) = page.group_size {
+        options.group_size = group_size;
+    }
+    let retrieval = match &state.reranker {
+        Some(reranker) => reranker.candidate_options(&options, candidates),
+        None => SearchOptions {
+            limit: candidates.unwrap_or(options.limit),
+            ..options.clone()
+        },
+    };
+    let mut results = state
+        .store
+        .search(embedding, &retrieval)
         .await
-        .inspect_err(|x| println!("Something went wrong: {x}"))
-        .unwrap();
+        .map_err(|x| x.to_string())?;
 
-    TableResult { results }
+    if let Some(reranker) = &state.reranker {
+        let top_k = top_k.unwrap_or(reranker.top_k());
+        results = reranker
+            .rerank_page(&prompt, results, &options, top_k)
+            .map_err(|x| x.to_string())?;
+    }
+
+    Ok(results)
 }
 
 pub async fn prompt_text(
     State(state): State<AppState>,
-    Json(Prompt { prompt }): Json<Prompt>,
+    Json(Prompt { prompt, .. }): Json<Prompt>,
 ) -> impl AxumResponse {
     println!("Request received!");
     Body::from_stream(state.ort.run_inference(prompt, 1024))
@@ -55,3 +308,19 @@ pub async fn prompt_text(
 pub async fn homepage() -> impl AskamaResponse {
     IndexFile
 }
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+struct StatsPage {
+    stats: Stats,
+}
+
+/// What is indexed in the collection and how healthy the index is.
+pub async fn stats(
+    State(state): State<AppState>,
+) -> Result<impl AskamaResponse, (StatusCode, String)> {
+    Stats::collect(state.store.as_ref())
+        .await
+        .map(|stats| StatsPage { stats })
+        .map_err(|x| (StatusCode::INTERNAL_SERVER_ERROR, x.to_string()))
+}